@@ -0,0 +1,300 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
+
+use crate::{make_hash, table, Equivalent, HashMap};
+
+/// Default length past which a chain makes an [`AdaptiveHashMap`] switch to SipHash.
+const DEFAULT_MAX_CHAIN: usize = 16;
+
+/// A hasher builder using the fast hasher builder `F` until it is
+/// [hardened](AdaptiveState::harden), from then on a randomly seeded SipHash.
+///
+/// This is what [`AdaptiveHashMap`] hashes its keys with.
+#[derive(Debug, Clone)]
+pub struct AdaptiveState<F> {
+    fast: F,
+    /// set once the keys are hashed with SipHash instead of `fast`.
+    secure: Option<RandomState>,
+}
+
+impl<F> AdaptiveState<F> {
+    pub fn new(fast: F) -> Self {
+        AdaptiveState { fast, secure: None }
+    }
+
+    /// Returns true once the keys are hashed with SipHash.
+    pub fn is_hardened(&self) -> bool {
+        self.secure.is_some()
+    }
+
+    /// Hashes with a randomly seeded SipHash from now on (every call draws a new seed).
+    ///
+    /// Every hash changes: a table hashed with this builder has to be rehashed afterwards.
+    pub fn harden(&mut self) {
+        self.secure = Some(RandomState::new());
+    }
+}
+
+impl<F: Default> Default for AdaptiveState<F> {
+    fn default() -> Self {
+        AdaptiveState::new(F::default())
+    }
+}
+
+impl<F: BuildHasher> BuildHasher for AdaptiveState<F> {
+    type Hasher = AdaptiveHasher<F::Hasher>;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        AdaptiveHasher(match &self.secure {
+            Some(secure) => Inner::Secure(secure.build_hasher()),
+            None => Inner::Fast(self.fast.build_hasher()),
+        })
+    }
+}
+
+/// The hasher built by [`AdaptiveState`]: either its fast hasher, or SipHash.
+pub struct AdaptiveHasher<H>(Inner<H>);
+
+enum Inner<H> {
+    Fast(H),
+    Secure(DefaultHasher),
+}
+
+macro_rules! forward_write {
+    ($($method:ident($t:ty)),*) => {
+        $(
+            fn $method(&mut self, n: $t) {
+                match &mut self.0 {
+                    Inner::Fast(hasher) => hasher.$method(n),
+                    Inner::Secure(hasher) => hasher.$method(n),
+                }
+            }
+        )*
+    };
+}
+
+impl<H: Hasher> Hasher for AdaptiveHasher<H> {
+    fn finish(&self) -> u64 {
+        match &self.0 {
+            Inner::Fast(hasher) => hasher.finish(),
+            Inner::Secure(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match &mut self.0 {
+            Inner::Fast(hasher) => hasher.write(bytes),
+            Inner::Secure(hasher) => hasher.write(bytes),
+        }
+    }
+
+    // the fast hasher may handle integers on their own (or only integers, like
+    // `IdentityHasher`), they must not go through `write`.
+    forward_write!(
+        write_u8(u8),
+        write_u16(u16),
+        write_u32(u32),
+        write_u64(u64),
+        write_u128(u128),
+        write_usize(usize)
+    );
+}
+
+/// A map hashing its keys with a fast hasher `F` (FxHash, FNV, identity, ...) until the keys
+/// start piling into the same buckets, and with a randomly seeded SipHash from then on.
+///
+/// Every insert checks the length of the chain its key went into. A chain longer than
+/// [`max_chain`](Self::max_chain) entries means the fast hasher is being defeated, whether by
+/// keys crafted to collide or by keys that just happen to suit it badly (e.g. integers sharing
+/// their low bits under an identity hash). The map then switches to SipHash for good, and
+/// rehashes all of its entries once. Normal keys keep the speed of the fast hasher, and a flood
+/// of colliding keys only costs one rebuild.
+///
+/// ```
+/// use rehash::hash::IdentityBuildHasher;
+/// use rehash::AdaptiveHashMap;
+///
+/// let mut map = AdaptiveHashMap::<u64, u64, IdentityBuildHasher<u64>>::new();
+/// // under an identity hash, multiples of a big power of two all land in bucket 0.
+/// for i in 0..100 {
+///     map.insert(i << 32, i);
+/// }
+/// assert!(map.is_hardened());
+/// assert_eq!(map.get(&(42 << 32)), Some(&42));
+/// ```
+pub struct AdaptiveHashMap<K, V, F> {
+    map: HashMap<K, V, AdaptiveState<F>>,
+    max_chain: usize,
+}
+
+impl<K, V, F: Default> AdaptiveHashMap<K, V, F> {
+    pub fn new() -> Self {
+        AdaptiveHashMap::with_hasher(F::default())
+    }
+}
+
+impl<K, V, F: Default> Default for AdaptiveHashMap<K, V, F> {
+    fn default() -> Self {
+        AdaptiveHashMap::new()
+    }
+}
+
+impl<K, V, F> AdaptiveHashMap<K, V, F> {
+    /// Creates an empty map which will use `fast` to hash keys, until they collide too much.
+    pub fn with_hasher(fast: F) -> Self {
+        AdaptiveHashMap {
+            map: HashMap::with_hasher(AdaptiveState::new(fast)),
+            max_chain: DEFAULT_MAX_CHAIN,
+        }
+    }
+
+    /// Switches to SipHash once a chain gets longer than `max_chain` entries (16 by default).
+    ///
+    /// Chains that long are practically impossible under a good hash: the table keeps less
+    /// than one entry per bucket on average.
+    pub fn set_max_chain(&mut self, max_chain: usize) {
+        self.max_chain = max_chain;
+    }
+
+    /// Returns the chain length past which the map switches to SipHash.
+    pub fn max_chain(&self) -> usize {
+        self.max_chain
+    }
+
+    /// Returns true once the map hashes its keys with SipHash.
+    pub fn is_hardened(&self) -> bool {
+        self.map.hash_builder.is_hardened()
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Gives back the underlying map, which keeps hashing with whichever hasher it was on.
+    pub fn into_inner(self) -> HashMap<K, V, AdaptiveState<F>> {
+        self.map
+    }
+}
+
+impl<K, V, F> AdaptiveHashMap<K, V, F>
+where
+    K: Hash + Eq,
+    F: BuildHasher,
+{
+    /// Inserts a key-value pair into the map, returning the old value of the key if it was
+    /// there.
+    ///
+    /// If the key's chain gets too long, the map switches to SipHash before returning.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.map.hash_builder, &key);
+        let hash_builder = &self.map.hash_builder;
+        let (bucket, index) = self.map.table.slot(
+            hash,
+            |(ekey, _)| ekey == &key,
+            |(key, _)| make_hash(hash_builder, key),
+        );
+        if let Some(index) = index {
+            let entry = &mut self.map.table.entries_mut()[index].value;
+            return Some(mem::replace(&mut entry.1, value));
+        }
+        self.map
+            .table
+            .insert_at(table::tag(hash), bucket, (key, value));
+        if !self.is_hardened() && self.map.table.chain_len(hash) > self.max_chain {
+            self.map.hash_builder.harden();
+            let hash_builder = &self.map.hash_builder;
+            self.map
+                .table
+                .rehash(|(key, _)| make_hash(hash_builder, key));
+        }
+        None
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get_mut(key)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Removes a key from the map, returning its value if it was there.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.remove(key)
+    }
+}
+
+impl<'a, K, V, F> IntoIterator for &'a AdaptiveHashMap<K, V, F> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = crate::Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        (&self.map).into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::BuildHasherDefault;
+
+    /// Hashes every key to 0.
+    #[derive(Default)]
+    struct Constant;
+
+    impl Hasher for Constant {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    #[test]
+    fn hardens_against_collisions() {
+        let mut map = AdaptiveHashMap::<u32, u32, BuildHasherDefault<Constant>>::new();
+        map.set_max_chain(4);
+        for i in 0..4 {
+            map.insert(i, i);
+        }
+        assert!(!map.is_hardened());
+
+        map.insert(4, 4);
+        assert!(map.is_hardened());
+        for i in 5..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.insert(7, 70), Some(7));
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get(&7), Some(&70));
+        assert_eq!(map.remove(&999), Some(999));
+        let longest = (0..999).map(|i| {
+            let hash = make_hash(&map.map.hash_builder, &i);
+            map.map.table.chain_len(hash)
+        });
+        assert!(longest.max().unwrap() <= 16);
+    }
+}
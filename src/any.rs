@@ -0,0 +1,77 @@
+use std::any::{Any, TypeId};
+
+use crate::hash::IdentityBuildHasher;
+use crate::HashMap;
+
+/// A map holding at most one value of every type, looked up by the type itself.
+///
+/// Values are stored as `Box<dyn Any>` under the `TypeId` of their type. A `TypeId` already is a
+/// well spread out number, so it is used as its own hash (see
+/// [`IdentityHasher`](crate::hash::IdentityHasher)) instead of going through SipHash.
+#[derive(Default)]
+pub struct AnyMap {
+    map: HashMap<TypeId, Box<dyn Any>, IdentityBuildHasher<TypeId>>,
+}
+
+impl AnyMap {
+    pub fn new() -> Self {
+        AnyMap::default()
+    }
+
+    /// Returns the number of values that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Stores `value` as the value of type `T`, returning the one that was there before.
+    pub fn insert<T: Any>(&mut self, value: T) -> Option<T> {
+        let old = self.map.insert(TypeId::of::<T>(), Box::new(value))?;
+        Some(*old.downcast().unwrap())
+    }
+
+    /// Returns a reference to the value of type `T`.
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Returns a mutable reference to the value of type `T`.
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Returns true if there is a value of type `T` in the map.
+    pub fn contains<T: Any>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Removes the value of type `T` from the map and returns it.
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        let value = self.map.remove(&TypeId::of::<T>())?;
+        Some(*value.downcast().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_value_per_type() {
+        let mut map = AnyMap::new();
+        assert_eq!(map.insert(42u32), None);
+        assert_eq!(map.insert("foo".to_string()), None);
+        assert_eq!(map.insert(43u32), Some(42));
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.get::<u32>(), Some(&43));
+        assert_eq!(map.get::<u64>(), None);
+        map.get_mut::<String>().unwrap().push_str("bar");
+        assert_eq!(map.remove::<String>().as_deref(), Some("foobar"));
+        assert!(!map.contains::<String>());
+        assert!(map.contains::<u32>());
+    }
+}
@@ -0,0 +1,572 @@
+//! A sharded hash map for async code, whose shard locks are waited for with `.await`.
+
+use std::cell::UnsafeCell;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use crate::concurrent::{default_shard_count, round_shard_count, shard_index, shard_refs};
+use crate::table::{self, HashTable};
+use crate::{make_hash, Equivalent};
+
+type ShardLock<K, V> = RwLock<HashTable<(K, V)>>;
+
+/// The async counterpart of [`ConcurrentHashMap`](crate::ConcurrentHashMap): a map split into
+/// shards, each behind its own lock, where waiting for a lock suspends the task instead of
+/// blocking the thread.
+///
+/// The guards returned by lookups and entries hold their shard's lock and are `Send`, so they can
+/// be kept across `.await` points: a task waiting for a shard another task holds lets the
+/// executor run that other task meanwhile, instead of blocking the thread it needs.
+///
+/// The locks don't depend on any runtime, they work with every executor. They wake every waiting
+/// task once a shard is free, and new readers wait behind a waiting writer, so a shard that is
+/// always being read doesn't keep writers out.
+///
+/// The number of shards is fixed when the map is created, see
+/// [`with_shard_count`](Self::with_shard_count): unlike a `ConcurrentHashMap`, the map can't be
+/// resharded afterwards.
+pub struct AsyncConcurrentHashMap<K, V, S = RandomState> {
+    shards: Box<[ShardLock<K, V>]>,
+    hash_builder: S,
+}
+
+impl<K, V> AsyncConcurrentHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        AsyncConcurrentHashMap::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty map split into `shard_count` shards, rounded up to a power of two.
+    ///
+    /// By default a map has four shards per thread the machine can run in parallel. More shards
+    /// let more tasks work on the map without waiting for each other, fewer shards take less
+    /// memory.
+    pub fn with_shard_count(shard_count: usize) -> Self {
+        AsyncConcurrentHashMap::with_shard_count_and_hasher(shard_count, RandomState::new())
+    }
+}
+
+impl<K, V, S> AsyncConcurrentHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        AsyncConcurrentHashMap::with_shard_count_and_hasher(default_shard_count(), hash_builder)
+    }
+
+    /// Creates an empty map split into `shard_count` shards, rounded up to a power of two, which
+    /// will use `hash_builder` to hash keys.
+    pub fn with_shard_count_and_hasher(shard_count: usize, hash_builder: S) -> Self {
+        AsyncConcurrentHashMap {
+            shards: (0..round_shard_count(shard_count))
+                .map(|_| RwLock::new(HashTable::new()))
+                .collect(),
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of shards the map is split into.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the number of items that are currently in the map.
+    ///
+    /// The shards are counted one after the other, so with other tasks inserting and removing
+    /// concurrently the result may never have been the exact size of the map.
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+        for shard in self.shards.iter() {
+            len += shard.read().await.len();
+        }
+        len
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    fn shard(&self, hash: u64) -> &ShardLock<K, V> {
+        &self.shards[shard_index(hash, self.shards.len())]
+    }
+}
+
+impl<K, V, S: Default> Default for AsyncConcurrentHashMap<K, V, S> {
+    fn default() -> Self {
+        AsyncConcurrentHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> AsyncConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map, returning the old value of the key if it was
+    /// there.
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        match self.entry(key).await {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        }
+    }
+
+    /// Gets the entry for `key`, holding the write lock of its shard until the entry (or the
+    /// reference it turns into) is dropped.
+    pub async fn entry(&self, key: K) -> Entry<'_, K, V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        let mut table = self.shard(hash).write().await;
+        let hash_builder = &self.hash_builder;
+        let (bucket, index) = table.slot(
+            hash,
+            |(ekey, _)| ekey == &key,
+            |(key, _)| make_hash(hash_builder, key),
+        );
+        match index {
+            Some(index) => Entry::Occupied(OccupiedEntry { table, index }),
+            None => Entry::Vacant(VacantEntry {
+                key,
+                tag: table::tag(hash),
+                bucket,
+                table,
+            }),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key, holding the read lock of its
+    /// shard.
+    pub async fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let table = self.shard(hash).read().await;
+        let index = table.find_index(hash, |(ekey, _)| key.equivalent(ekey))?;
+        Some(Ref { table, index })
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key, holding the write lock
+    /// of its shard.
+    pub async fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, K, V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let table = self.shard(hash).write().await;
+        let index = table.find_index(hash, |(ekey, _)| key.equivalent(ekey))?;
+        Some(RefMut { table, index })
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).await.is_some()
+    }
+
+    /// Removes a key from the map, returning its value if it was there.
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let mut table = self.shard(hash).write().await;
+        table
+            .remove(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+}
+
+shard_refs!("an" "AsyncConcurrentHashMap", ReadGuard, WriteGuard);
+
+/// Number of readers of a lock held for writing.
+const WRITER: usize = usize::MAX;
+
+/// A reader-writer lock that is waited for asynchronously.
+///
+/// Its state sits behind a regular mutex, only ever held for a few instructions. Every task
+/// waiting for the lock leaves one waker in it, and all of them are woken once it is free again:
+/// the ones that lose the race simply wait again. As soon as a writer waits, new readers wait
+/// behind it, so that a steady stream of readers can't keep writers out.
+struct RwLock<T> {
+    state: Mutex<LockState>,
+    value: UnsafeCell<T>,
+}
+
+struct LockState {
+    /// number of readers holding the lock, [`WRITER`] if a writer does.
+    readers: usize,
+    /// number of writers among `waiters`.
+    writers_waiting: usize,
+    /// the waker of every pending [`Acquire`], with its id.
+    waiters: Vec<(u64, Waker)>,
+    next_id: u64,
+}
+
+impl LockState {
+    /// Takes the waker of a waiter back out of the lock.
+    fn forget(&mut self, id: u64, write: bool) {
+        let pos = self.waiters.iter().position(|&(wid, _)| wid == id).unwrap();
+        self.waiters.swap_remove(pos);
+        if write {
+            self.writers_waiting -= 1;
+        }
+    }
+
+    /// The wakers to call once the lock can be taken again.
+    fn wakers(&self) -> Vec<Waker> {
+        self.waiters
+            .iter()
+            .map(|(_, waker)| waker.clone())
+            .collect()
+    }
+}
+
+// SAFETY: the value is only reached through guards, which the lock hands out like
+// `std::sync::RwLock` does.
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    fn new(value: T) -> Self {
+        RwLock {
+            state: Mutex::new(LockState {
+                readers: 0,
+                writers_waiting: 0,
+                waiters: Vec::new(),
+                next_id: 0,
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, LockState> {
+        // the state is never left half-updated, a panic can't poison anything.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    async fn read(&self) -> ReadGuard<'_, T> {
+        Acquire::new(self, false).await;
+        ReadGuard { lock: self }
+    }
+
+    async fn write(&self) -> WriteGuard<'_, T> {
+        Acquire::new(self, true).await;
+        WriteGuard { lock: self }
+    }
+
+    fn release(&self, write: bool) {
+        let wakers = {
+            let mut state = self.state();
+            if write {
+                state.readers = 0;
+            } else {
+                state.readers -= 1;
+            }
+            // while readers remain, only writers (and the readers behind them) wait, and they
+            // still can't get in.
+            if state.readers == 0 {
+                state.wakers()
+            } else {
+                Vec::new()
+            }
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// A pending [`RwLock::read`] or [`RwLock::write`].
+///
+/// While it waits, it keeps a single waker in the lock, replaced if it is polled with another
+/// one, and takes it back once it gets the lock or is dropped.
+struct Acquire<'a, T> {
+    lock: &'a RwLock<T>,
+    write: bool,
+    /// id of the waker left in the lock, while waiting.
+    waiting: Option<u64>,
+}
+
+impl<'a, T> Acquire<'a, T> {
+    fn new(lock: &'a RwLock<T>, write: bool) -> Self {
+        Acquire {
+            lock,
+            write,
+            waiting: None,
+        }
+    }
+}
+
+impl<T> Future for Acquire<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let lock = self.lock;
+        let mut state = lock.state();
+        let free = if self.write {
+            state.readers == 0
+        } else {
+            state.readers != WRITER && state.writers_waiting == 0
+        };
+        if free {
+            if let Some(id) = self.waiting.take() {
+                state.forget(id, self.write);
+            }
+            if self.write {
+                state.readers = WRITER;
+            } else {
+                state.readers += 1;
+            }
+            return Poll::Ready(());
+        }
+
+        match self.waiting {
+            Some(id) => {
+                let (_, waker) = state
+                    .waiters
+                    .iter_mut()
+                    .find(|(wid, _)| *wid == id)
+                    .unwrap();
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push((id, cx.waker().clone()));
+                if self.write {
+                    state.writers_waiting += 1;
+                }
+                self.waiting = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Acquire<'_, T> {
+    fn drop(&mut self) {
+        let id = match self.waiting {
+            Some(id) => id,
+            None => return,
+        };
+        let wakers = {
+            let mut state = self.lock.state();
+            state.forget(id, self.write);
+            // the readers queued behind the last waiting writer can go in now.
+            if self.write && state.writers_waiting == 0 && state.readers != WRITER {
+                state.wakers()
+            } else {
+                Vec::new()
+            }
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+struct ReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the lock is held for reading, no writer can be around.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(false);
+    }
+}
+
+struct WriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the lock is held for writing, nobody else can be around.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the lock is held for writing, nobody else can be around.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread;
+
+    /// Counts how often it has been woken.
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Runs `future` on the current thread, parking it while the future is pending.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        block_on(async {
+            let map = AsyncConcurrentHashMap::with_shard_count(5);
+            assert_eq!(map.shard_count(), 8);
+            assert_eq!(map.insert("foo", 42).await, None);
+            assert_eq!(map.insert("foo", 43).await, Some(42));
+            *map.get_mut("foo").await.unwrap() += 1;
+            assert_eq!(*map.get("foo").await.unwrap(), 44);
+            assert_eq!(*map.entry("bar").await.or_default(), 0);
+            assert_eq!(map.len().await, 2);
+            assert_eq!(map.remove("foo").await, Some(44));
+            assert!(!map.contains_key("foo").await);
+        });
+    }
+
+    #[test]
+    fn one_waker_per_waiter() {
+        let lock = RwLock::new(0);
+        let waker = Waker::from(Arc::new(Counter(AtomicUsize::new(0))));
+        let mut cx = Context::from_waker(&waker);
+
+        let writing = block_on(lock.write());
+        let mut read = Box::pin(lock.read());
+        for _ in 0..100 {
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(lock.state().waiters.len(), 1);
+        // a cancelled wait takes its waker back.
+        drop(read);
+        assert!(lock.state().waiters.is_empty());
+        drop(writing);
+    }
+
+    #[test]
+    fn writers_go_before_new_readers() {
+        let lock = RwLock::new(0);
+        let waker = Waker::from(Arc::new(Counter(AtomicUsize::new(0))));
+        let mut cx = Context::from_waker(&waker);
+
+        let reading = block_on(lock.read());
+        let mut write = Box::pin(lock.write());
+        assert!(write.as_mut().poll(&mut cx).is_pending());
+        let mut read = Box::pin(lock.read());
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+
+        drop(reading);
+        let mut writing = match write.as_mut().poll(&mut cx) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("the readers are gone"),
+        };
+        *writing += 1;
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+        drop(writing);
+        match read.as_mut().poll(&mut cx) {
+            Poll::Ready(guard) => assert_eq!(*guard, 1),
+            Poll::Pending => panic!("the writer is gone"),
+        };
+
+        // readers don't wait for a writer that gave up.
+        let reading = block_on(lock.read());
+        let mut write = Box::pin(lock.write());
+        assert!(write.as_mut().poll(&mut cx).is_pending());
+        drop(write);
+        let _also_reading = block_on(lock.read());
+        drop(reading);
+    }
+
+    #[test]
+    fn waits_for_the_shard() {
+        let map = AsyncConcurrentHashMap::new();
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let entry = block_on(map.entry(1));
+        let mut get = pin!(map.get(&1));
+        assert!(get.as_mut().poll(&mut cx).is_pending());
+
+        entry.or_insert(10);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        match get.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => assert_eq!(*value.unwrap(), 10),
+            Poll::Pending => panic!("the shard is free"),
+        };
+    }
+
+    #[test]
+    fn guards_can_cross_threads() {
+        let map = Arc::new(AsyncConcurrentHashMap::new());
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    block_on(async {
+                        for i in 0..1000 {
+                            map.entry(i % 10).await.and_modify(|n| *n += 1).or_insert(1);
+                        }
+                    })
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        block_on(async {
+            for i in 0..10 {
+                assert_eq!(*map.get(&i).await.unwrap(), 400);
+            }
+        });
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::hash_map::RandomState;
+use std::marker::PhantomData;
+
+use crate::table::{HashTable, DEFAULT_MAX_LOAD};
+use crate::HashMap;
+
+/// Configures a [`HashMap`] before creating it, see [`HashMap::builder`].
+///
+/// ```
+/// use rehash::{HashMap, SeedableState};
+///
+/// let map: HashMap<u32, &str, _> = HashMap::builder()
+///     .capacity(1000)
+///     .max_load_factor(2.0)
+///     .hasher(SeedableState::with_seed(42))
+///     .build();
+/// assert!(map.bucket_count() >= 500);
+/// ```
+pub struct HashMapBuilder<K, V, S = RandomState> {
+    capacity: usize,
+    max_load: f32,
+    hash_builder: S,
+    // `fn() -> _` so that the builder doesn't own (nor need to drop) any K or V.
+    map: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> HashMap<K, V, RandomState> {
+    /// Returns a builder for a map, with the same settings as [`HashMap::new`] until they are
+    /// changed.
+    pub fn builder() -> HashMapBuilder<K, V, RandomState> {
+        HashMapBuilder::new()
+    }
+}
+
+impl<K, V> HashMapBuilder<K, V, RandomState> {
+    pub fn new() -> Self {
+        HashMapBuilder {
+            capacity: 0,
+            max_load: DEFAULT_MAX_LOAD,
+            hash_builder: RandomState::new(),
+            map: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Default for HashMapBuilder<K, V, RandomState> {
+    fn default() -> Self {
+        HashMapBuilder::new()
+    }
+}
+
+impl<K, V, S> HashMapBuilder<K, V, S> {
+    /// Makes room for at least `capacity` items up front, so that they can be inserted without
+    /// resizing.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Lets the map grow only once it has more than `max_load` entries per bucket on average,
+    /// instead of 3/4. Higher values save memory, at the cost of longer chains to search.
+    ///
+    /// [`build`](Self::build) panics if `max_load` is below 0.1 (ten buckets per entry), or
+    /// isn't a finite number.
+    pub fn max_load_factor(mut self, max_load: f32) -> Self {
+        self.max_load = max_load;
+        self
+    }
+
+    /// Hashes the keys with `hash_builder` instead of a [`RandomState`].
+    pub fn hasher<S2>(self, hash_builder: S2) -> HashMapBuilder<K, V, S2> {
+        HashMapBuilder {
+            capacity: self.capacity,
+            max_load: self.max_load,
+            hash_builder,
+            map: PhantomData,
+        }
+    }
+
+    /// Creates the map.
+    ///
+    /// # Panics
+    ///
+    /// If the maximum load factor is below 0.1, or isn't a finite number.
+    pub fn build(self) -> HashMap<K, V, S> {
+        HashMap {
+            table: HashTable::with_capacity_and_max_load(self.capacity, self.max_load),
+            hash_builder: self.hash_builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table;
+
+    #[test]
+    fn max_load_factor() {
+        let mut map = HashMap::builder().max_load_factor(4.0).build();
+        map.extend((0..1000).map(|i| (i, i)));
+        assert_eq!(map.max_load_factor(), 4.0);
+        assert!(map.bucket_count() <= 256);
+        assert_eq!(map.get(&999), Some(&999));
+
+        // the setting survives clones and splits.
+        let even = map.clone().split_off(|key, _| key % 2 == 0);
+        assert_eq!(even.max_load_factor(), 4.0);
+
+        let map: HashMap<u32, u32> = HashMap::builder().capacity(100).build();
+        assert_eq!(map.bucket_count(), table::buckets_for(100));
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_zero_load_factor() {
+        HashMap::<u32, u32>::builder().max_load_factor(0.0).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "has to be at least")]
+    fn rejects_tiny_load_factor() {
+        // would ask for more buckets than fit in a `usize`.
+        HashMap::<u32, u32>::builder()
+            .capacity(10)
+            .max_load_factor(1e-30)
+            .build();
+    }
+}
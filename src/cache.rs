@@ -0,0 +1,627 @@
+//! A bounded cache, with the entry to evict when it is full picked by a pluggable policy.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
+
+use crate::table::HashTable;
+use crate::{make_hash, Equivalent};
+
+/// Decides which entry a full [`Cache`] evicts.
+///
+/// The cache tells the policy about everything happening to its entries, identified by slot: a
+/// small integer below the cache's capacity, which stays the same for as long as the entry is in
+/// the cache, and is handed out again once it's gone.
+pub trait EvictionPolicy {
+    /// An entry has been inserted in `slot`.
+    fn inserted(&mut self, slot: usize);
+
+    /// The entry in `slot` has been looked up (or its value replaced).
+    fn accessed(&mut self, slot: usize) {
+        let _ = slot;
+    }
+
+    /// The entry in `slot` has been removed, be it by the user or evicted.
+    fn removed(&mut self, slot: usize);
+
+    /// Returns the slot of the entry to evict. Only called when the cache is full, and followed
+    /// by a call to [`removed`](Self::removed) for the returned slot.
+    fn victim(&mut self) -> usize;
+}
+
+/// A map holding at most `capacity` entries: inserting a new key in a full cache evicts the entry
+/// `P` picks first.
+///
+/// Entries can also be given a weight (e.g. their size in bytes) with
+/// [`push_weighted`](Self::push_weighted), in which case the capacity bounds their total weight
+/// instead of their number. An entry inserted without one weighs 1.
+///
+/// The entries live in slots of a `Vec`, and the hash table only holds slot numbers, which
+/// policies use to keep track of the entries without cloning keys.
+pub struct Cache<K, V, P = Lru, S = RandomState> {
+    table: HashTable<usize>,
+    slots: Vec<Option<(K, V)>>,
+    /// weight of the entry in every slot.
+    weights: Vec<usize>,
+    /// slots whose entry has been removed, to be reused first.
+    free: Vec<usize>,
+    capacity: usize,
+    /// total weight of the entries.
+    weight: usize,
+    policy: P,
+    hash_builder: S,
+}
+
+impl<K, V, P: EvictionPolicy + Default> Cache<K, V, P, RandomState> {
+    /// Creates an empty cache holding at most `capacity` entries.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        Cache::with_policy_and_hasher(capacity, P::default(), RandomState::new())
+    }
+}
+
+impl<K, V, P: EvictionPolicy> Cache<K, V, P, RandomState> {
+    /// Creates an empty cache holding at most `capacity` entries, evicting them as `policy`
+    /// decides.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn with_policy(capacity: usize, policy: P) -> Self {
+        Cache::with_policy_and_hasher(capacity, policy, RandomState::new())
+    }
+
+    /// Creates an empty cache for entries inserted with [`push_weighted`](Self::push_weighted),
+    /// whose weights add up to at most `max_weight`.
+    ///
+    /// Unlike the other constructors, this doesn't allocate room for `max_weight` entries up
+    /// front.
+    ///
+    /// # Panics
+    ///
+    /// If `max_weight` is 0.
+    pub fn with_max_weight(max_weight: usize, policy: P) -> Self {
+        Cache::empty(max_weight, policy, RandomState::new())
+    }
+}
+
+impl<K, V, P: EvictionPolicy, S> Cache<K, V, P, S> {
+    /// Creates an empty cache holding at most `capacity` entries, evicting them as `policy`
+    /// decides and hashing keys with `hash_builder`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn with_policy_and_hasher(capacity: usize, policy: P, hash_builder: S) -> Self {
+        let mut cache = Cache::empty(capacity, policy, hash_builder);
+        cache.table = HashTable::with_capacity(capacity);
+        cache.slots.reserve_exact(capacity);
+        cache.weights.reserve_exact(capacity);
+        cache
+    }
+
+    /// Creates a cache that allocates as entries come in.
+    fn empty(capacity: usize, policy: P, hash_builder: S) -> Self {
+        assert!(capacity > 0, "a cache needs room for at least one entry");
+        Cache {
+            table: HashTable::new(),
+            slots: Vec::new(),
+            weights: Vec::new(),
+            free: Vec::new(),
+            capacity,
+            weight: 0,
+            policy,
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Returns the total weight of the entries the cache holds before it starts evicting: the
+    /// number of entries, unless they have been given weights.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the total weight of the entries in the cache.
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    fn entry(&self, slot: usize) -> &(K, V) {
+        self.slots[slot].as_ref().unwrap()
+    }
+}
+
+impl<K, V, P, S> Cache<K, V, P, S>
+where
+    K: Hash + Eq,
+    P: EvictionPolicy,
+    S: BuildHasher,
+{
+    fn find<Q>(&self, key: &Q) -> Option<usize>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let slots = &self.slots;
+        self.table
+            .find(hash, |&slot| key.equivalent(&slots[slot].as_ref().unwrap().0))
+            .copied()
+    }
+
+    /// Inserts a key-value pair, returning the old value of the key if it was in the cache.
+    ///
+    /// If it wasn't and the cache is full, the entry picked by the policy is dropped to make
+    /// room, see [`push`](Self::push) to get it back instead.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.find(&key) {
+            Some(slot) => {
+                self.replaced(slot);
+                let entry = self.slots[slot].as_mut().unwrap();
+                Some(mem::replace(&mut entry.1, value))
+            }
+            None => {
+                self.push(key, value);
+                None
+            }
+        }
+    }
+
+    /// Inserts a key-value pair, returning the entry it pushed out: the old one of the key, or
+    /// else the one evicted to make room if the cache was full.
+    pub fn push(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(slot) = self.find(&key) {
+            self.replaced(slot);
+            let entry = self.slots[slot].as_mut().unwrap();
+            return Some(mem::replace(entry, (key, value)));
+        }
+
+        // every entry weighs at least 1, so evicting one is enough to make room.
+        let evicted = if self.weight == self.capacity {
+            let victim = self.policy.victim();
+            Some(self.remove_slot(victim))
+        } else {
+            None
+        };
+        self.insert_new(key, value, 1);
+        evicted
+    }
+
+    /// Inserts a key-value pair weighing `weight`, evicting entries until the total weight fits
+    /// in the capacity again. Returns the entries pushed out, starting with the old one of the
+    /// key if it was in the cache.
+    ///
+    /// The policy sees the entry as a new one, even if it replaces the old value of the key. An
+    /// entry heavier than the whole capacity isn't inserted: it comes back last, after the old
+    /// entry of the key.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is 0.
+    pub fn push_weighted(&mut self, key: K, value: V, weight: usize) -> Vec<(K, V)> {
+        assert!(weight > 0, "an entry has to weigh at least 1");
+        let mut pushed_out = Vec::new();
+        if let Some(slot) = self.find(&key) {
+            pushed_out.push(self.remove_slot(slot));
+        }
+        if weight > self.capacity {
+            pushed_out.push((key, value));
+            return pushed_out;
+        }
+        while self.capacity - self.weight < weight {
+            let victim = self.policy.victim();
+            pushed_out.push(self.remove_slot(victim));
+        }
+        self.insert_new(key, value, weight);
+        pushed_out
+    }
+
+    /// Lets the policy know the entry in `slot` is getting a new value, which weighs 1.
+    fn replaced(&mut self, slot: usize) {
+        self.policy.accessed(slot);
+        self.weight = self.weight - self.weights[slot] + 1;
+        self.weights[slot] = 1;
+    }
+
+    fn insert_new(&mut self, key: K, value: V, weight: usize) {
+        let hash = make_hash(&self.hash_builder, &key);
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some((key, value));
+                self.weights[slot] = weight;
+                slot
+            }
+            None => {
+                self.slots.push(Some((key, value)));
+                self.weights.push(weight);
+                self.slots.len() - 1
+            }
+        };
+        self.weight += weight;
+        let (slots, hash_builder) = (&self.slots, &self.hash_builder);
+        self.table.insert_unique(hash, slot, |&slot| {
+            make_hash(hash_builder, &slots[slot].as_ref().unwrap().0)
+        });
+        self.policy.inserted(slot);
+    }
+
+    /// Returns the value of `key`, and lets the policy know it has been used.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let slot = self.find(key)?;
+        self.policy.accessed(slot);
+        Some(&self.entry(slot).1)
+    }
+
+    /// Returns a mutable reference to the value of `key`, and lets the policy know it has been
+    /// used.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let slot = self.find(key)?;
+        self.policy.accessed(slot);
+        Some(&mut self.slots[slot].as_mut().unwrap().1)
+    }
+
+    /// Returns the value of `key`, without the policy knowing: peeking doesn't make an entry
+    /// any less likely to be evicted.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.find(key).map(|slot| &self.entry(slot).1)
+    }
+
+    /// Returns true if `key` is in the cache, without the policy knowing.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Removes `key` from the cache, returning its value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let slot = self.find(key)?;
+        Some(self.remove_slot(slot).1)
+    }
+
+    fn remove_slot(&mut self, slot: usize) -> (K, V) {
+        let hash = make_hash(&self.hash_builder, &self.entry(slot).0);
+        self.table.remove(hash, |&other| other == slot).unwrap();
+        self.policy.removed(slot);
+        self.weight -= self.weights[slot];
+        self.free.push(slot);
+        self.slots[slot].take().unwrap()
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+/// A doubly linked list of slots, oldest first.
+struct SlotList {
+    /// previous and next slot of every slot.
+    links: Vec<(usize, usize)>,
+    head: usize,
+    tail: usize,
+}
+
+impl SlotList {
+    fn new() -> Self {
+        SlotList {
+            links: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn push_back(&mut self, slot: usize) {
+        if slot >= self.links.len() {
+            self.links.resize(slot + 1, (NIL, NIL));
+        }
+        self.links[slot] = (self.tail, NIL);
+        match self.tail {
+            NIL => self.head = slot,
+            tail => self.links[tail].1 = slot,
+        }
+        self.tail = slot;
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = self.links[slot];
+        match prev {
+            NIL => self.head = next,
+            prev => self.links[prev].1 = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.links[next].0 = prev,
+        }
+    }
+}
+
+/// Evicts the least recently used entry.
+pub struct Lru {
+    list: SlotList,
+}
+
+impl Lru {
+    pub fn new() -> Self {
+        Lru {
+            list: SlotList::new(),
+        }
+    }
+}
+
+impl Default for Lru {
+    fn default() -> Self {
+        Lru::new()
+    }
+}
+
+impl EvictionPolicy for Lru {
+    fn inserted(&mut self, slot: usize) {
+        self.list.push_back(slot);
+    }
+
+    fn accessed(&mut self, slot: usize) {
+        self.list.unlink(slot);
+        self.list.push_back(slot);
+    }
+
+    fn removed(&mut self, slot: usize) {
+        self.list.unlink(slot);
+    }
+
+    fn victim(&mut self) -> usize {
+        self.list.head
+    }
+}
+
+/// Evicts the entry that has been in the cache the longest, no matter how often it is used.
+pub struct Fifo {
+    list: SlotList,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Fifo {
+            list: SlotList::new(),
+        }
+    }
+}
+
+impl Default for Fifo {
+    fn default() -> Self {
+        Fifo::new()
+    }
+}
+
+impl EvictionPolicy for Fifo {
+    fn inserted(&mut self, slot: usize) {
+        self.list.push_back(slot);
+    }
+
+    fn removed(&mut self, slot: usize) {
+        self.list.unlink(slot);
+    }
+
+    fn victim(&mut self) -> usize {
+        self.list.head
+    }
+}
+
+/// Evicts the least frequently used entry, the least recently used one among those used as
+/// often.
+///
+/// Counts start over when an entry is evicted: a key coming back isn't favored for having been
+/// popular before.
+#[derive(Default)]
+pub struct Lfu {
+    /// use count and last use of every slot.
+    uses: Vec<(u64, u64)>,
+    /// `(count, last use, slot)` of every entry, the victim first.
+    order: BTreeSet<(u64, u64, usize)>,
+    clock: u64,
+}
+
+impl Lfu {
+    pub fn new() -> Self {
+        Lfu::default()
+    }
+
+    fn touch(&mut self, slot: usize, count: u64) {
+        self.clock += 1;
+        self.uses[slot] = (count, self.clock);
+        self.order.insert((count, self.clock, slot));
+    }
+}
+
+impl EvictionPolicy for Lfu {
+    fn inserted(&mut self, slot: usize) {
+        if slot >= self.uses.len() {
+            self.uses.resize(slot + 1, (0, 0));
+        }
+        self.touch(slot, 1);
+    }
+
+    fn accessed(&mut self, slot: usize) {
+        let (count, last) = self.uses[slot];
+        self.order.remove(&(count, last, slot));
+        self.touch(slot, count + 1);
+    }
+
+    fn removed(&mut self, slot: usize) {
+        let (count, last) = self.uses[slot];
+        self.order.remove(&(count, last, slot));
+    }
+
+    fn victim(&mut self) -> usize {
+        self.order.iter().next().unwrap().2
+    }
+}
+
+/// Evicts an entry picked at random.
+///
+/// Randomness comes from a xorshift generator, seeded like [`RandomState`] unless a seed is
+/// given: it's cheap, but nothing an attacker can't predict.
+pub struct Random {
+    slots: Vec<usize>,
+    /// position of every slot in `slots`.
+    positions: Vec<usize>,
+    state: u64,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        Random::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Creates a policy evicting the same entries on every run.
+    pub fn with_seed(seed: u64) -> Self {
+        Random {
+            slots: Vec::new(),
+            positions: Vec::new(),
+            // xorshift gets stuck on 0.
+            state: seed | 1,
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new()
+    }
+}
+
+impl EvictionPolicy for Random {
+    fn inserted(&mut self, slot: usize) {
+        if slot >= self.positions.len() {
+            self.positions.resize(slot + 1, 0);
+        }
+        self.positions[slot] = self.slots.len();
+        self.slots.push(slot);
+    }
+
+    fn removed(&mut self, slot: usize) {
+        let position = self.positions[slot];
+        self.slots.swap_remove(position);
+        if let Some(&moved) = self.slots.get(position) {
+            self.positions[moved] = position;
+        }
+    }
+
+    fn victim(&mut self) -> usize {
+        let pick = self.next() % self.slots.len() as u64;
+        self.slots[pick as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        let mut cache: Cache<_, _> = Cache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.push("c", 3), Some(("b", 2)));
+        assert_eq!(cache.peek("a"), Some(&1));
+        // peeking doesn't count as a use.
+        assert_eq!(cache.push("d", 4), Some(("a", 1)));
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.insert("c", 30), Some(3));
+        assert_eq!(cache.push("e", 5), Some(("d", 4)));
+        assert_eq!(cache.remove("c"), Some(30));
+        assert_eq!(cache.push("f", 6), None);
+        assert!(cache.contains_key("e") && cache.contains_key("f"));
+    }
+
+    #[test]
+    fn weighted() {
+        let mut cache = Cache::with_max_weight(100, Fifo::new());
+        assert!(cache.push_weighted("a", 1, 40).is_empty());
+        assert!(cache.push_weighted("b", 2, 40).is_empty());
+        assert_eq!(cache.weight(), 80);
+        assert_eq!(cache.push_weighted("c", 3, 90), vec![("a", 1), ("b", 2)]);
+        assert_eq!(cache.push_weighted("d", 4, 101), vec![("d", 4)]);
+        assert_eq!(cache.push_weighted("c", 30, 50), vec![("c", 3)]);
+        assert_eq!(cache.weight(), 50);
+
+        // entries inserted without a weight weigh 1.
+        assert_eq!(cache.insert("c", 31), Some(30));
+        assert_eq!(cache.weight(), 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn fifo() {
+        let mut cache = Cache::with_policy(2, Fifo::new());
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.push("c", 3), Some(("a", 1)));
+        assert_eq!(cache.push("d", 4), Some(("b", 2)));
+    }
+
+    #[test]
+    fn lfu() {
+        let mut cache = Cache::with_policy(3, Lfu::new());
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        cache.insert("c", 3);
+        for _ in 0..3 {
+            cache.get("a");
+        }
+        cache.get("c");
+        assert_eq!(cache.push("d", 4), Some(("b", 2)));
+        // a new entry has been used the least, ties go to the least recently used.
+        assert_eq!(cache.push("e", 5), Some(("d", 4)));
+        cache.get("e");
+        assert_eq!(cache.push("f", 6), Some(("c", 3)));
+    }
+
+    #[test]
+    fn random() {
+        let mut cache = Cache::with_policy(10, Random::with_seed(42));
+        for i in 0..1000 {
+            let evicted = cache.push(i, i);
+            assert_eq!(evicted.is_some(), i >= 10);
+            assert!(cache.contains_key(&i));
+        }
+        assert_eq!(cache.len(), 10);
+        assert_eq!(cache.slots.len(), 10);
+    }
+}
@@ -0,0 +1,227 @@
+//! The small vector the entry indices of a bucket are chained in.
+
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::{fmt, ptr, slice};
+
+/// Number of entries a chain holds without allocating.
+///
+/// With at most 3/4 of an entry per bucket, fewer than 5% of the buckets get a third entry
+/// (assuming the keys are spread evenly), so almost no chain ever has to allocate.
+pub(crate) const INLINE: usize = 2;
+
+/// A vector keeping up to [`INLINE`] items inline, and moving them to the heap once it needs
+/// more room.
+///
+/// A chain that has moved to the heap stays there, even if items are removed again: it may well
+/// grow back, and moving between the two would just be churn.
+pub(crate) struct Chain<T> {
+    repr: Repr<T>,
+}
+
+enum Repr<T> {
+    /// the first `len` items are initialized.
+    Inline {
+        len: usize,
+        items: [MaybeUninit<T>; INLINE],
+    },
+    Heap(Vec<T>),
+}
+
+impl<T> Chain<T> {
+    pub(crate) const fn new() -> Self {
+        Chain {
+            repr: Repr::Inline {
+                len: 0,
+                items: [const { MaybeUninit::uninit() }; INLINE],
+            },
+        }
+    }
+
+    /// Returns how many items the chain has room for on the heap, 0 while they are inline.
+    pub(crate) fn heap_capacity(&self) -> usize {
+        match &self.repr {
+            Repr::Inline { .. } => 0,
+            Repr::Heap(items) => items.capacity(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        match &mut self.repr {
+            Repr::Inline { len, items } if *len < INLINE => {
+                items[*len].write(item);
+                *len += 1;
+            }
+            Repr::Inline { len, items } => {
+                let mut heap = Vec::with_capacity(2 * INLINE);
+                // SAFETY: the items are initialized, and moved out only once: `repr` is
+                // overwritten right away (without dropping anything, it's all `MaybeUninit`).
+                heap.extend(
+                    items[..*len]
+                        .iter()
+                        .map(|item| unsafe { item.assume_init_read() }),
+                );
+                heap.push(item);
+                self.repr = Repr::Heap(heap);
+            }
+            Repr::Heap(items) => items.push(item),
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        match &mut self.repr {
+            Repr::Inline { len: 0, .. } => None,
+            Repr::Inline { len, items } => {
+                *len -= 1;
+                // SAFETY: the item was initialized, and is now past `len` so it won't be read
+                // (or dropped) again.
+                Some(unsafe { items[*len].assume_init_read() })
+            }
+            Repr::Heap(items) => items.pop(),
+        }
+    }
+
+    /// Removes the item at `index`, replacing it by the last one.
+    pub(crate) fn swap_remove(&mut self, index: usize) -> T {
+        let last = self.len() - 1;
+        self.swap(index, last);
+        self.pop().unwrap()
+    }
+}
+
+impl<T> Drop for Chain<T> {
+    fn drop(&mut self) {
+        if let Repr::Inline { len, items } = &mut self.repr {
+            // SAFETY: the first `len` items are initialized, and dropped only here.
+            unsafe { ptr::drop_in_place(slice_assume_init_mut(&mut items[..*len])) };
+        }
+    }
+}
+
+impl<T> Deref for Chain<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match &self.repr {
+            // SAFETY: the first `len` items are initialized.
+            Repr::Inline { len, items } => unsafe {
+                slice::from_raw_parts(items.as_ptr().cast(), *len)
+            },
+            Repr::Heap(items) => items,
+        }
+    }
+}
+
+impl<T> DerefMut for Chain<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match &mut self.repr {
+            // SAFETY: the first `len` items are initialized.
+            Repr::Inline { len, items } => unsafe { slice_assume_init_mut(&mut items[..*len]) },
+            Repr::Heap(items) => items,
+        }
+    }
+}
+
+/// `MaybeUninit::slice_assume_init_mut`, which isn't stable yet.
+///
+/// SAFETY: every item of `items` has to be initialized.
+unsafe fn slice_assume_init_mut<T>(items: &mut [MaybeUninit<T>]) -> &mut [T] {
+    unsafe { slice::from_raw_parts_mut(items.as_mut_ptr().cast(), items.len()) }
+}
+
+impl<T> Default for Chain<T> {
+    fn default() -> Self {
+        Chain::new()
+    }
+}
+
+impl<T: Clone> Clone for Chain<T> {
+    fn clone(&self) -> Self {
+        let mut chain = Chain::new();
+        if let Repr::Heap(items) = &self.repr {
+            chain.repr = Repr::Heap(items.clone());
+        } else {
+            for item in self.iter() {
+                chain.push(item.clone());
+            }
+        }
+        chain
+    }
+
+    /// Overwrites `self` with a copy of `source`, keeping the heap allocation `self` has.
+    fn clone_from(&mut self, source: &Self) {
+        match &mut self.repr {
+            Repr::Heap(items) => {
+                items.clear();
+                items.extend_from_slice(source);
+            }
+            Repr::Inline { .. } => *self = source.clone(),
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Chain<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Chain<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Chain<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Chain<T> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn spills_to_the_heap() {
+        let mut chain = Chain::new();
+        for i in 0..INLINE {
+            chain.push(i);
+        }
+        assert_eq!(chain.heap_capacity(), 0);
+        chain.push(INLINE);
+        assert!(chain.heap_capacity() > INLINE);
+        assert_eq!(&*chain, &(0..=INLINE).collect::<Vec<_>>()[..]);
+
+        assert_eq!(chain.swap_remove(0), 0);
+        assert_eq!(chain[0], INLINE);
+        assert_eq!(chain.len(), INLINE);
+    }
+
+    #[test]
+    fn drops_every_item_once() {
+        let item = Rc::new(());
+        let mut chain = Chain::new();
+        chain.push(item.clone());
+        chain.push(item.clone());
+        drop(chain.swap_remove(0));
+        assert_eq!(Rc::strong_count(&item), 2);
+
+        let copy = chain.clone();
+        assert_eq!(Rc::strong_count(&item), 3);
+        drop(copy);
+        drop(chain);
+        assert_eq!(Rc::strong_count(&item), 1);
+    }
+}
@@ -0,0 +1,731 @@
+//! A hash map that can be shared between threads, split into independently locked shards.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+
+use crate::table::{self, HashTable, Slot};
+use crate::{make_hash, Equivalent};
+
+/// Number of shards of a new map, if the parallelism of the machine can't be found out.
+const FALLBACK_SHARDS: usize = 16;
+
+type ShardLock<K, V> = RwLock<HashTable<(K, V)>>;
+type ShardArray<K, V> = Box<[ShardLock<K, V>]>;
+
+/// A hash map split into shards, each behind its own `RwLock`, so that threads working on keys
+/// in different shards don't wait for each other.
+///
+/// A key is hashed once: the upper bits of the hash pick its shard, and the shard's table uses
+/// the hash as it is. Every method takes `&self`; lookups return guards holding their shard's
+/// read (or write) lock, so don't keep them around while accessing other keys of the map from
+/// the same thread.
+///
+/// By default a map has four shards per thread the machine can run in parallel, see
+/// [`with_shard_count`](Self::with_shard_count) and [`reshard`](Self::reshard) to pick another
+/// number, up front or while the map is in use.
+///
+/// A lock poisoned by a panic (e.g. in a key's `Hash` or `Eq`) is used anyway, just like a
+/// `HashMap` can still be used after such a panic was caught.
+pub struct ConcurrentHashMap<K, V, S = RandomState> {
+    /// the shards in use, allocated with `Box::into_raw`.
+    shards: AtomicPtr<ShardArray<K, V>>,
+    /// the shards [`reshard`](Self::reshard) replaced: emptied, but kept alive for the guards
+    /// (and the threads about to lock them) that may still point into them. Locked for the whole
+    /// of a reshard, so that only one runs at a time.
+    retired: Mutex<Vec<*mut ShardArray<K, V>>>,
+    hash_builder: S,
+}
+
+// SAFETY: the raw pointers are owned boxes, the map is `Send` and `Sync` whenever a
+// `Box<[RwLock<HashTable<(K, V)>>]>` and `S` would be.
+unsafe impl<K: Send, V: Send, S: Send> Send for ConcurrentHashMap<K, V, S> {}
+unsafe impl<K, V, S> Sync for ConcurrentHashMap<K, V, S>
+where
+    K: Send + Sync,
+    V: Send + Sync,
+    S: Sync,
+{
+}
+
+impl<K, V> ConcurrentHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        ConcurrentHashMap::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty map split into `shard_count` shards, rounded up to a power of two.
+    ///
+    /// More shards let more threads work on the map without waiting for each other, fewer
+    /// shards take less memory: every shard has its own lock and table, even when empty.
+    pub fn with_shard_count(shard_count: usize) -> Self {
+        ConcurrentHashMap::with_shard_count_and_hasher(shard_count, RandomState::new())
+    }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        ConcurrentHashMap::with_shard_count_and_hasher(default_shard_count(), hash_builder)
+    }
+
+    /// Creates an empty map split into `shard_count` shards, rounded up to a power of two, which
+    /// will use `hash_builder` to hash keys.
+    pub fn with_shard_count_and_hasher(shard_count: usize, hash_builder: S) -> Self {
+        ConcurrentHashMap {
+            shards: AtomicPtr::new(Box::into_raw(Box::new(new_shards(shard_count)))),
+            retired: Mutex::new(Vec::new()),
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    ///
+    /// The shards are counted one after the other, so with other threads inserting and removing
+    /// concurrently the result may never have been the exact size of the map.
+    pub fn len(&self) -> usize {
+        self.current().iter().map(|shard| read(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current().iter().all(|shard| read(shard).is_empty())
+    }
+
+    /// Returns the number of shards the map is split into.
+    pub fn shard_count(&self) -> usize {
+        self.current().len()
+    }
+
+    /// Returns an iterator over the shards, to lock them one at a time.
+    ///
+    /// The iterator goes over the shards in use when it is created: if the map is resharded in
+    /// the meantime, it goes on over the old shards, which are empty from then on.
+    pub fn shards(&self) -> Shards<'_, K, V> {
+        Shards {
+            shards: self.current().iter(),
+        }
+    }
+
+    /// Returns an iterator read-locking one shard after the other, so that a background job can
+    /// walk the whole map while other threads keep using the rest of it.
+    ///
+    /// Each shard is locked when the iterator gets to it, and unlocked when its guard is dropped.
+    /// The walk as a whole isn't a snapshot: keys can move in and out of shards that were already
+    /// visited or are still to come, and a reshard in the middle of it moves all of them.
+    pub fn iter_shards(&self) -> IterShards<'_, K, V> {
+        IterShards {
+            shards: self.current().iter(),
+        }
+    }
+
+    /// Returns the shards in use.
+    fn current(&self) -> &[ShardLock<K, V>] {
+        // SAFETY: shard arrays are only freed when the map is dropped.
+        unsafe { &*self.shards.load(Ordering::Acquire) }
+    }
+
+    /// Locks the shard of `hash` with `lock`, making sure it still is in use once locked.
+    fn lock_shard<'a, G>(&'a self, hash: u64, lock: impl Fn(&'a ShardLock<K, V>) -> G) -> G {
+        loop {
+            let shards = self.current();
+            let guard = lock(&shards[shard_index(hash, shards.len())]);
+            // a reshard replaces the shards while it has all of them locked, so they can't be
+            // replaced while we hold one.
+            if ptr::eq(shards, self.current()) {
+                return guard;
+            }
+        }
+    }
+}
+
+impl<K, V, S> Drop for ConcurrentHashMap<K, V, S> {
+    fn drop(&mut self) {
+        let retired = self
+            .retired
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for &mut shards in retired.iter_mut().chain([self.shards.get_mut()]) {
+            // SAFETY: every array was allocated with `Box::into_raw`, and is only freed here.
+            drop(unsafe { Box::from_raw(shards) });
+        }
+    }
+}
+
+/// Number of shards of a new map: four per thread the machine can run in parallel, so that
+/// threads working on the map at the same time rarely need the same shard.
+pub(crate) fn default_shard_count() -> usize {
+    thread::available_parallelism().map_or(FALLBACK_SHARDS, |threads| {
+        threads.get().saturating_mul(4).next_power_of_two()
+    })
+}
+
+/// Number of shards a map asked for `shard_count` shards gets: a power of two, at least 1.
+pub(crate) fn round_shard_count(shard_count: usize) -> usize {
+    shard_count.max(1).next_power_of_two()
+}
+
+fn new_shards<K, V>(shard_count: usize) -> ShardArray<K, V> {
+    (0..round_shard_count(shard_count))
+        .map(|_| RwLock::new(HashTable::new()))
+        .collect()
+}
+
+/// The shard a key with hash `hash` goes to, out of `shards` (a power of two).
+pub(crate) fn shard_index(hash: u64, shards: usize) -> usize {
+    // the upper bits, the table picks buckets with the lower ones.
+    let bits = shards.trailing_zeros();
+    hash.checked_shr(u64::BITS - bits).unwrap_or(0) as usize
+}
+
+impl<K, V, S: Default> Default for ConcurrentHashMap<K, V, S> {
+    fn default() -> Self {
+        ConcurrentHashMap::with_hasher(S::default())
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        }
+    }
+
+    /// Gets the entry for `key`, holding the write lock of its shard until the entry (or the
+    /// reference it turns into) is dropped.
+    ///
+    /// Everything done through the entry happens atomically with respect to other threads.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        let mut table = self.lock_shard(hash, write);
+        let hash_builder = &self.hash_builder;
+        let (bucket, index) = table.slot(
+            hash,
+            |(ekey, _)| ekey == &key,
+            |(key, _)| make_hash(hash_builder, key),
+        );
+        match index {
+            Some(index) => Entry::Occupied(OccupiedEntry { table, index }),
+            None => Entry::Vacant(VacantEntry {
+                key,
+                tag: table::tag(hash),
+                bucket,
+                table,
+            }),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key, holding the read lock of its
+    /// shard.
+    pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let table = self.lock_shard(hash, read);
+        let index = table.find_index(hash, |(ekey, _)| key.equivalent(ekey))?;
+        Some(Ref { table, index })
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key, holding the write lock
+    /// of its shard.
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, K, V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let table = self.lock_shard(hash, write);
+        let index = table.find_index(hash, |(ekey, _)| key.equivalent(ekey))?;
+        Some(RefMut { table, index })
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let mut table = self.lock_shard(hash, write);
+        table
+            .remove(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Splits the map into `shard_count` shards instead, rounded up to a power of two, moving
+    /// every entry to its new shard. Entries are moved, not cloned, and keys are only hashed
+    /// again if their shard's table didn't keep their whole hash.
+    ///
+    /// Other threads can keep using the map: the reshard waits for the guards they hold, and
+    /// locks every shard while it moves the entries, so they wait for it in turn. Don't call it
+    /// while holding a guard of the map, that guard's shard never gets free.
+    ///
+    /// The old shards can't be freed while other threads may still be looking at them, so they
+    /// stay allocated (empty) until the map is dropped: reshard once in a while, not in a loop.
+    pub fn reshard(&self, shard_count: usize) {
+        let mut retired = self.retired.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut old: Vec<_> = self.current().iter().map(write).collect();
+        let mut new = new_shards(shard_count);
+        let hash_builder = &self.hash_builder;
+        let hasher = |(key, _): &(K, V)| make_hash(hash_builder, key);
+        for table in &mut old {
+            for (hash, entry) in mem::take(&mut **table).into_hashed(hasher) {
+                let index = shard_index(hash, new.len());
+                new[index]
+                    .get_mut()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert_unique(hash, entry, hasher);
+            }
+        }
+        let new = Box::into_raw(Box::new(new));
+        retired.push(self.shards.swap(new, Ordering::AcqRel));
+        // threads waiting for the old shards find them replaced once they get them.
+        drop(old);
+    }
+}
+
+/// One shard of a [`ConcurrentHashMap`], returned by [`ConcurrentHashMap::shards`].
+pub struct Shard<'a, K, V> {
+    lock: &'a ShardLock<K, V>,
+}
+
+impl<'a, K, V> Shard<'a, K, V> {
+    /// Locks the shard for reading, waiting for writers to be done.
+    pub fn read(&self) -> ShardReadGuard<'a, K, V> {
+        ShardReadGuard {
+            table: read(self.lock),
+        }
+    }
+
+    /// Locks the shard for writing, waiting for readers and writers to be done.
+    pub fn write(&self) -> ShardWriteGuard<'a, K, V> {
+        ShardWriteGuard {
+            table: write(self.lock),
+        }
+    }
+}
+
+/// The entries of a shard, read-locked.
+pub struct ShardReadGuard<'a, K, V> {
+    table: RwLockReadGuard<'a, HashTable<(K, V)>>,
+}
+
+impl<K, V> ShardReadGuard<'_, K, V> {
+    /// Returns the number of items in the shard.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Returns an iterator over the entries of the shard, in arbitrary order.
+    pub fn iter(&self) -> crate::Iter<'_, K, V> {
+        crate::Iter {
+            entries: self.table.iter(),
+        }
+    }
+}
+
+/// The entries of a shard, write-locked.
+pub struct ShardWriteGuard<'a, K, V> {
+    table: RwLockWriteGuard<'a, HashTable<(K, V)>>,
+}
+
+impl<K, V> ShardWriteGuard<'_, K, V> {
+    /// Returns the number of items in the shard.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Returns an iterator over the entries of the shard, in arbitrary order.
+    pub fn iter(&self) -> crate::Iter<'_, K, V> {
+        crate::Iter {
+            entries: self.table.iter(),
+        }
+    }
+
+    /// Returns an iterator over the entries of the shard with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            entries: self.table.entries_mut().iter_mut(),
+        }
+    }
+
+    /// Retains only the entries of the shard for which `f` returns true, removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.table.retain(|(key, value)| f(key, value));
+    }
+}
+
+pub struct Shards<'a, K, V> {
+    shards: slice::Iter<'a, ShardLock<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Shards<'a, K, V> {
+    type Item = Shard<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.shards.next().map(|lock| Shard { lock })
+    }
+}
+
+pub struct IterShards<'a, K, V> {
+    shards: slice::Iter<'a, ShardLock<K, V>>,
+}
+
+impl<'a, K, V> Iterator for IterShards<'a, K, V> {
+    type Item = ShardReadGuard<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.shards
+            .next()
+            .map(|lock| ShardReadGuard { table: read(lock) })
+    }
+}
+
+pub struct IterMut<'a, K, V> {
+    entries: slice::IterMut<'a, Slot<(K, V)>>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next()
+            .map(|slot| (&slot.value.0, &mut slot.value.1))
+    }
+}
+
+/// Defines the guards a sharded map hands out: `Ref`, `RefMut` and the `Entry` types, holding
+/// their shard's lock through `$read` and `$write` guards of a `HashTable<(K, V)>`.
+///
+/// [`ConcurrentHashMap`] and the async map only differ in their locks, this keeps one copy of the
+/// code for both.
+macro_rules! shard_refs {
+    ($a:literal $map:literal, $read:ident, $write:ident) => {
+        #[doc = concat!("A reference to a value of ", $a, " [`", $map, "`], holding the read lock")]
+        /// of its shard.
+        pub struct Ref<'a, K, V> {
+            table: $read<'a, $crate::table::HashTable<(K, V)>>,
+            index: usize,
+        }
+
+        impl<K, V> Ref<'_, K, V> {
+            pub fn key(&self) -> &K {
+                &self.table.entries()[self.index].value.0
+            }
+        }
+
+        impl<K, V> std::ops::Deref for Ref<'_, K, V> {
+            type Target = V;
+
+            fn deref(&self) -> &V {
+                &self.table.entries()[self.index].value.1
+            }
+        }
+
+        #[doc = concat!("A mutable reference to a value of ", $a, " [`", $map, "`], holding the")]
+        /// write lock of its shard.
+        pub struct RefMut<'a, K, V> {
+            table: $write<'a, $crate::table::HashTable<(K, V)>>,
+            index: usize,
+        }
+
+        impl<K, V> RefMut<'_, K, V> {
+            pub fn key(&self) -> &K {
+                &self.table.entries()[self.index].value.0
+            }
+        }
+
+        impl<K, V> std::ops::Deref for RefMut<'_, K, V> {
+            type Target = V;
+
+            fn deref(&self) -> &V {
+                &self.table.entries()[self.index].value.1
+            }
+        }
+
+        impl<K, V> std::ops::DerefMut for RefMut<'_, K, V> {
+            fn deref_mut(&mut self) -> &mut V {
+                &mut self.table.entries_mut()[self.index].value.1
+            }
+        }
+
+        #[doc = concat!("A view into a single entry of ", $a, " [`", $map, "`], holding the write")]
+        /// lock of its shard.
+        ///
+        #[doc = concat!("Returned by [`", $map, "::entry`].")]
+        pub enum Entry<'a, K, V> {
+            Occupied(OccupiedEntry<'a, K, V>),
+            Vacant(VacantEntry<'a, K, V>),
+        }
+
+        /// An entry whose key is in the map.
+        pub struct OccupiedEntry<'a, K, V> {
+            table: $write<'a, $crate::table::HashTable<(K, V)>>,
+            /// position of the entry in the entries of `table`.
+            index: usize,
+        }
+
+        /// An entry whose key is not in the map (yet).
+        pub struct VacantEntry<'a, K, V> {
+            key: K,
+            /// tag of the hash of `key`.
+            tag: $crate::table::HashTag,
+            /// the bucket `key` belongs to, the map has already made sure it has room for one
+            /// more item.
+            bucket: usize,
+            table: $write<'a, $crate::table::HashTable<(K, V)>>,
+        }
+
+        impl<'a, K, V> Entry<'a, K, V> {
+            /// Ensures a value is in the entry by inserting `default` if it is vacant, and
+            /// returns a mutable reference to the value.
+            pub fn or_insert(self, default: V) -> RefMut<'a, K, V> {
+                match self {
+                    Entry::Occupied(entry) => entry.into_ref(),
+                    Entry::Vacant(entry) => entry.insert(default),
+                }
+            }
+
+            /// Like [`or_insert`](Entry::or_insert), but only computes the value if it is needed.
+            ///
+            /// `default` runs with the shard locked: it must not access the map.
+            pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> RefMut<'a, K, V> {
+                match self {
+                    Entry::Occupied(entry) => entry.into_ref(),
+                    Entry::Vacant(entry) => entry.insert(default()),
+                }
+            }
+
+            /// Calls `f` with the value if the entry is occupied, before any potential inserts.
+            pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+                if let Entry::Occupied(ref mut entry) = self {
+                    f(entry.get_mut());
+                }
+                self
+            }
+        }
+
+        impl<'a, K, V: Default> Entry<'a, K, V> {
+            /// Ensures a value is in the entry by inserting the default value if it is vacant,
+            /// and returns a mutable reference to the value.
+            pub fn or_default(self) -> RefMut<'a, K, V> {
+                self.or_insert_with(V::default)
+            }
+        }
+
+        impl<'a, K, V> OccupiedEntry<'a, K, V> {
+            /// Returns the key stored in the map.
+            pub fn key(&self) -> &K {
+                &self.table.entries()[self.index].value.0
+            }
+
+            pub fn get(&self) -> &V {
+                &self.table.entries()[self.index].value.1
+            }
+
+            pub fn get_mut(&mut self) -> &mut V {
+                &mut self.table.entries_mut()[self.index].value.1
+            }
+
+            /// Sets the value of the entry, returning the old value.
+            pub fn insert(&mut self, value: V) -> V {
+                std::mem::replace(self.get_mut(), value)
+            }
+
+            /// Converts the entry into a mutable reference to the value, which keeps the shard
+            /// locked.
+            pub fn into_ref(self) -> RefMut<'a, K, V> {
+                RefMut {
+                    table: self.table,
+                    index: self.index,
+                }
+            }
+
+            /// Takes the value out of the map.
+            pub fn remove(mut self) -> V {
+                self.table.remove_at(self.index).value.1
+            }
+        }
+
+        impl<'a, K, V> VacantEntry<'a, K, V> {
+            pub fn key(&self) -> &K {
+                &self.key
+            }
+
+            /// Inserts the key with `value`, returning a mutable reference to the value.
+            pub fn insert(mut self, value: V) -> RefMut<'a, K, V> {
+                let index = self
+                    .table
+                    .insert_at(self.tag, self.bucket, (self.key, value));
+                RefMut {
+                    table: self.table,
+                    index,
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "async")]
+pub(crate) use shard_refs;
+
+shard_refs!("a" "ConcurrentHashMap", RwLockReadGuard, RwLockWriteGuard);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn insert_get_remove() {
+        let map = ConcurrentHashMap::new();
+        assert_eq!(map.insert("foo", 42), None);
+        assert_eq!(map.insert("foo", 43), Some(42));
+        assert_eq!(*map.get("foo").unwrap(), 43);
+        *map.get_mut("foo").unwrap() += 1;
+        assert_eq!(map.get("foo").unwrap().key(), &"foo");
+        assert_eq!(map.remove("foo"), Some(44));
+        assert!(map.get("foo").is_none());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn entry_is_atomic() {
+        let map = ConcurrentHashMap::new();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..1000 {
+                        map.entry(i % 10).and_modify(|n| *n += 1).or_insert(1);
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 10);
+        for i in 0..10 {
+            assert_eq!(*map.get(&i).unwrap(), 400);
+        }
+
+        match map.entry(0) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove(), 400),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(*map.entry(0).or_default(), 0);
+    }
+
+    #[test]
+    fn shard_by_shard() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.shards().count(), map.shard_count());
+        let total: usize = map.iter_shards().map(|shard| shard.len()).sum();
+        assert_eq!(total, 100);
+
+        for shard in map.shards() {
+            let mut shard = shard.write();
+            for (_, value) in shard.iter_mut() {
+                *value *= 2;
+            }
+            shard.retain(|key, _| key % 2 == 0);
+        }
+        let sum: i32 = map
+            .iter_shards()
+            .map(|shard| shard.iter().map(|(_, v)| v).sum::<i32>())
+            .sum();
+        assert_eq!(sum, (0..100).filter(|i| i % 2 == 0).map(|i| i * 2).sum());
+        assert_eq!(map.len(), 50);
+    }
+
+    #[test]
+    fn reshard() {
+        let map = ConcurrentHashMap::with_shard_count(3);
+        assert_eq!(map.shard_count(), 4);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        for &count in &[64, 1, 8] {
+            map.reshard(count);
+            assert_eq!(map.shard_count(), count);
+            assert_eq!(map.len(), 1000);
+            for i in 0..1000 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+        }
+        assert!(map.iter_shards().all(|shard| !shard.is_empty()));
+    }
+
+    #[test]
+    fn reshard_while_in_use() {
+        let map = ConcurrentHashMap::with_shard_count(1);
+        thread::scope(|scope| {
+            for t in 0..4 {
+                let map = &map;
+                scope.spawn(move || {
+                    for i in 0..2000 {
+                        map.insert(t * 2000 + i, i);
+                        *map.entry(10_000 + t).or_insert(0) += 1;
+                    }
+                });
+            }
+            let mut counts = [2, 64, 8].iter().cycle();
+            while map.len() < 8004 {
+                map.reshard(*counts.next().unwrap());
+            }
+        });
+        map.reshard(32);
+        assert_eq!(map.shard_count(), 32);
+        assert_eq!(map.len(), 8004);
+        for t in 0..4 {
+            assert_eq!(*map.get(&(10_000 + t)).unwrap(), 2000);
+            for i in 0..2000 {
+                assert_eq!(*map.get(&(t * 2000 + i)).unwrap(), i);
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Conversions between [`HashMap`] and `std::collections::HashMap`.
+
+use std::collections;
+use std::hash::{BuildHasher, Hash};
+
+use crate::{make_hash, HashMap};
+
+impl<K, V, S> From<collections::HashMap<K, V, S>> for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Clone,
+{
+    /// Moves the entries of a std map into a new map using a clone of its hasher builder.
+    ///
+    /// The keys of a std map are unique already, so they are inserted without looking for
+    /// duplicates, into a table sized once for all of them.
+    fn from(map: collections::HashMap<K, V, S>) -> Self {
+        let mut new = HashMap::with_capacity_and_hasher(map.len(), map.hasher().clone());
+        let hash_builder = &new.hash_builder;
+        let hasher = |(key, _): &(K, V)| make_hash(hash_builder, key);
+        for (key, value) in map {
+            let hash = make_hash(hash_builder, &key);
+            new.table.insert_unique(hash, (key, value), hasher);
+        }
+        new
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for collections::HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Moves the entries into a std map, which takes over the hasher builder.
+    fn from(map: HashMap<K, V, S>) -> Self {
+        let HashMap {
+            table,
+            hash_builder,
+        } = map;
+        let mut new = collections::HashMap::with_capacity_and_hasher(table.len(), hash_builder);
+        new.extend(table);
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SeedableState;
+
+    #[test]
+    fn round_trip() {
+        let mut std_map = collections::HashMap::with_hasher(SeedableState::with_seed(7));
+        for i in 0..100 {
+            std_map.insert(i, i.to_string());
+        }
+
+        let map = HashMap::from(std_map);
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&42).map(String::as_str), Some("42"));
+        assert_eq!(map.hasher().seed(), 7);
+
+        let std_map = collections::HashMap::from(map);
+        assert_eq!(std_map.len(), 100);
+        assert_eq!(std_map.get(&99).map(String::as_str), Some("99"));
+        assert_eq!(std_map.hasher().seed(), 7);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::sync::Arc;
+
+use crate::{Equivalent, INITIAL_BUCKETS};
+
+type Bucket<K, V> = Arc<Vec<(K, V)>>;
+
+/// A hash map with copy-on-write buckets, for maps that are cloned a lot and mutated rarely.
+///
+/// The bucket table and every bucket sit behind an `Arc`, so `clone` is a single reference count
+/// bump. The first mutation of a clone copies the table of bucket pointers and the one bucket it
+/// touches; every other bucket stays shared with the maps it was cloned from.
+pub struct CowHashMap<K, V> {
+    buckets: Arc<Vec<Bucket<K, V>>>,
+    /// number of items in the hash-map (for easy access)
+    items: usize,
+    hash_builder: RandomState,
+}
+
+impl<K, V> CowHashMap<K, V> {
+    pub fn new() -> Self {
+        CowHashMap {
+            // allocation happens during initial insert.
+            buckets: Arc::new(Vec::new()),
+            items: 0,
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+}
+
+impl<K, V> Default for CowHashMap<K, V> {
+    fn default() -> Self {
+        CowHashMap::new()
+    }
+}
+
+/// Cloning only bumps the reference count of the bucket table.
+impl<K, V> Clone for CowHashMap<K, V> {
+    fn clone(&self) -> Self {
+        CowHashMap {
+            buckets: self.buckets.clone(),
+            items: self.items,
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+}
+
+impl<K, V> CowHashMap<K, V>
+where
+    K: Hash + Eq,
+{
+    fn bucket<Q>(&self, key: &Q) -> Option<usize>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        Some((self.hash_builder.hash_one(key) % self.buckets.len() as u64) as usize)
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let bucket = self.bucket(key)?;
+        self.buckets[bucket]
+            .iter()
+            .find(|(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K, V> CowHashMap<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        // check if resize is needed
+        if self.buckets.is_empty() || self.items > 3 * self.buckets.len() / 4 {
+            self.resize();
+        }
+
+        let bucket = self.bucket(&key).expect("");
+        let bucket = Arc::make_mut(&mut Arc::make_mut(&mut self.buckets)[bucket]);
+        for (ekey, evalue) in bucket.iter_mut() {
+            if ekey == &key {
+                return Some(mem::replace(evalue, value));
+            }
+        }
+
+        self.items += 1;
+        bucket.push((key, value));
+        None
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let bucket = self.bucket(key)?;
+        // check before copying anything, removing a missing key shouldn't unshare buckets.
+        let i = self.buckets[bucket]
+            .iter()
+            .position(|(ekey, _)| key.equivalent(ekey))?;
+        let bucket = Arc::make_mut(&mut Arc::make_mut(&mut self.buckets)[bucket]);
+        self.items -= 1;
+        Some(bucket.swap_remove(i).1)
+    }
+
+    fn resize(&mut self) {
+        let target_size = match self.buckets.len() {
+            0 => INITIAL_BUCKETS,
+            n => 2 * n,
+        };
+
+        let mut new_buckets: Vec<Vec<(K, V)>> = (0..target_size).map(|_| Vec::new()).collect();
+        let old_buckets = mem::take(&mut self.buckets);
+        // entries are only cloned if some other map still shares their bucket.
+        for bucket in Arc::unwrap_or_clone(old_buckets) {
+            for (key, value) in Arc::unwrap_or_clone(bucket) {
+                let bucket = (self.hash_builder.hash_one(&key) % target_size as u64) as usize;
+                new_buckets[bucket].push((key, value));
+            }
+        }
+
+        self.buckets = Arc::new(new_buckets.into_iter().map(Arc::new).collect());
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    buckets: std::slice::Iter<'a, Bucket<K, V>>,
+    bucket: std::slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.bucket.next() {
+                Some((k, v)) => break Some((k, v)),
+                // no more items in the bucket, move to next bucket
+                None => self.bucket = self.buckets.next()?.iter(),
+            }
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a CowHashMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            buckets: self.buckets.iter(),
+            bucket: [].iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_are_independent() {
+        let mut map = CowHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let snapshot = map.clone();
+        assert!(Arc::ptr_eq(&map.buckets, &snapshot.buckets));
+
+        map.insert(0, 1000);
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.get(&0), Some(&1000));
+        assert_eq!(map.len(), 99);
+        assert_eq!(snapshot.get(&0), Some(&0));
+        assert_eq!(snapshot.get(&1), Some(&1));
+        assert_eq!(snapshot.len(), 100);
+        assert_eq!((&snapshot).into_iter().count(), 100);
+    }
+
+    #[test]
+    fn mutation_copies_only_touched_bucket() {
+        let mut map = CowHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let snapshot = map.clone();
+        map.insert(0, 1000);
+
+        let shared = map
+            .buckets
+            .iter()
+            .zip(snapshot.buckets.iter())
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count();
+        assert_eq!(shared, map.buckets.len() - 1);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::slice;
+
+use crate::{make_hash, Equivalent};
+
+/// Number of slots allocated by the first insert.
+const INITIAL_SLOTS: usize = 8;
+/// An insert gives up moving entries around after this many evictions.
+const MAX_KICKS: usize = 32;
+/// Entries that found no slot wait here, until the next resize.
+const STASH: usize = 4;
+
+type Slot<K, V> = (u64, K, V);
+
+/// A hash map using cuckoo hashing: every key can only live in one of two slots.
+///
+/// The two slots of a key are picked by the two halves of its hash, so a lookup looks at (at
+/// most) two slots plus a stash of a few entries, whatever the keys are: the worst case is just
+/// as fast as the average one. That's paid on insert: when both slots of a key are taken, it
+/// kicks out the entry in one of them, which moves to its other slot, possibly kicking out
+/// another entry, and so on. After too many evictions the entry left over goes to the stash, and
+/// once the stash is full too the table doubles. (Only keys with exactly the same hash can make
+/// the stash grow past a few entries.)
+///
+/// The table is kept at most half full, which is what two-choice cuckoo hashing needs for inserts
+/// to stay cheap.
+pub struct CuckooHashMap<K, V, S = RandomState> {
+    slots: Vec<Option<Slot<K, V>>>,
+    stash: Vec<Slot<K, V>>,
+    /// number of items in the hash-map (for easy access)
+    items: usize,
+    hash_builder: S,
+}
+
+/// The two slots of a key with hash `hash`, out of `len`.
+fn positions(hash: u64, len: usize) -> (usize, usize) {
+    let len = len as u64;
+    (
+        ((hash as u32 as u64) % len) as usize,
+        ((hash >> 32) % len) as usize,
+    )
+}
+
+impl<K, V> CuckooHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        CuckooHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> CuckooHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        CuckooHashMap {
+            // allocation happens during initial insert.
+            slots: Vec::new(),
+            stash: Vec::new(),
+            items: 0,
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+}
+
+impl<K, V, S: Default> Default for CuckooHashMap<K, V, S> {
+    fn default() -> Self {
+        CuckooHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> CuckooHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        if let Some(evalue) = self.find_mut(hash, |ekey| ekey == &key) {
+            return Some(mem::replace(evalue, value));
+        }
+
+        if 2 * (self.items + 1) > self.slots.len() {
+            self.grow();
+        }
+        self.items += 1;
+        self.place((hash, key, value));
+        None
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let hash = make_hash(&self.hash_builder, key);
+        let (a, b) = positions(hash, self.slots.len());
+        let is_key = |&&(ehash, ref ekey, _): &&Slot<K, V>| ehash == hash && key.equivalent(ekey);
+        self.slots[a]
+            .iter()
+            .chain(&self.slots[b])
+            .chain(&self.stash)
+            .find(is_key)
+            .map(|(_, _, v)| v)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        self.find_mut(hash, |ekey| key.equivalent(ekey))
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let hash = make_hash(&self.hash_builder, key);
+        let (a, b) = positions(hash, self.slots.len());
+        for i in [a, b] {
+            let is_key = |&(ehash, ref ekey, _): &Slot<K, V>| ehash == hash && key.equivalent(ekey);
+            if self.slots[i].as_ref().is_some_and(is_key) {
+                self.items -= 1;
+                return self.slots[i].take().map(|(_, _, v)| v);
+            }
+        }
+        let i = self
+            .stash
+            .iter()
+            .position(|(ehash, ekey, _)| *ehash == hash && key.equivalent(ekey))?;
+        self.items -= 1;
+        Some(self.stash.swap_remove(i).2)
+    }
+
+    fn find_mut<F>(&mut self, hash: u64, mut eq: F) -> Option<&mut V>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let (a, b) = positions(hash, self.slots.len());
+        // `a` and `b` may be the same slot, look them up one at a time.
+        for i in [a, b] {
+            if matches!(self.slots[i], Some((ehash, ref ekey, _)) if ehash == hash && eq(ekey)) {
+                return self.slots[i].as_mut().map(|(_, _, v)| v);
+            }
+        }
+        self.stash
+            .iter_mut()
+            .find(|(ehash, ekey, _)| *ehash == hash && eq(ekey))
+            .map(|(_, _, v)| v)
+    }
+
+    /// Puts `entry` in the table, growing it if it doesn't fit.
+    fn place(&mut self, mut entry: Slot<K, V>) {
+        loop {
+            entry = match self.try_place(entry) {
+                None => return,
+                Some(homeless) => homeless,
+            };
+            // a mostly empty table that still can't fit the entry won't do better when bigger:
+            // too many keys share the same hash.
+            if self.stash.len() < STASH || self.slots.len() > 8 * self.items {
+                self.stash.push(entry);
+                return;
+            }
+            self.grow();
+        }
+    }
+
+    /// Puts `entry` in one of its slots, moving other entries out of the way. Returns the entry
+    /// that is left without a slot when that takes too many evictions.
+    fn try_place(&mut self, mut entry: Slot<K, V>) -> Option<Slot<K, V>> {
+        let len = self.slots.len();
+        let (a, b) = positions(entry.0, len);
+        for i in [a, b] {
+            if self.slots[i].is_none() {
+                self.slots[i] = Some(entry);
+                return None;
+            }
+        }
+
+        let mut i = a;
+        for _ in 0..MAX_KICKS {
+            // take the slot, the entry that was there moves to its other slot.
+            entry = self.slots[i].replace(entry).unwrap();
+            let (a, b) = positions(entry.0, len);
+            i = if i == a { b } else { a };
+            if self.slots[i].is_none() {
+                self.slots[i] = Some(entry);
+                return None;
+            }
+        }
+        Some(entry)
+    }
+
+    fn grow(&mut self) {
+        let target_size = match self.slots.len() {
+            0 => INITIAL_SLOTS,
+            n => 2 * n,
+        };
+        let old_slots = mem::replace(&mut self.slots, (0..target_size).map(|_| None).collect());
+        let old_stash = mem::take(&mut self.stash);
+        // every entry keeps its hash, nothing needs hashing again.
+        for entry in old_slots.into_iter().flatten().chain(old_stash) {
+            self.place(entry);
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    slots: slice::Iter<'a, Option<Slot<K, V>>>,
+    stash: slice::Iter<'a, Slot<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots
+            .by_ref()
+            .flatten()
+            .chain(&mut self.stash)
+            .next()
+            .map(|(_, k, v)| (k, v))
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a CuckooHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            slots: self.slots.iter(),
+            stash: self.stash.iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = CuckooHashMap::new();
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        assert_eq!(map.len(), 1000);
+        assert!(2 * map.len() <= map.slots.len());
+        for i in 0..1000 {
+            assert_eq!(map.get(&i).copied(), Some(if i == 7 { 0 } else { i * 2 }));
+        }
+        assert_eq!(map.get(&1000), None);
+
+        *map.get_mut(&7).unwrap() = 70;
+        assert_eq!(map.remove(&7), Some(70));
+        assert_eq!(map.remove(&7), None);
+        assert_eq!(map.len(), 999);
+        assert_eq!((&map).into_iter().count(), 999);
+    }
+
+    #[test]
+    fn equal_hashes_use_the_stash() {
+        #[derive(Default)]
+        struct Constant;
+        impl std::hash::Hasher for Constant {
+            fn finish(&self) -> u64 {
+                7
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        // every key has the same two slots, the other keys can only go to the stash.
+        let hash_builder = std::hash::BuildHasherDefault::<Constant>::default();
+        let mut map = CuckooHashMap::with_hasher(hash_builder);
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.stash.len(), 8);
+        for i in 0..10 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        assert_eq!(map.remove(&3), Some(3));
+        assert_eq!(map.get(&3), None);
+    }
+}
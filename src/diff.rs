@@ -0,0 +1,74 @@
+use std::hash::{BuildHasher, Hash};
+
+use crate::HashMap;
+
+/// The differences between two maps, as returned by [`HashMap::diff`]: what changes going from
+/// the first map to the second.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapDiff<'a, K, V> {
+    /// entries only in the second map.
+    pub added: Vec<(&'a K, &'a V)>,
+    /// entries only in the first map.
+    pub removed: Vec<(&'a K, &'a V)>,
+    /// keys in both maps with different values: the key, its value in the first map, and its
+    /// value in the second.
+    pub changed: Vec<(&'a K, &'a V, &'a V)>,
+}
+
+impl<K, V> MapDiff<'_, K, V> {
+    /// Returns true if the two maps hold the same entries.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    /// Compares `self` with `other`: lists the entries added, removed and changed going from
+    /// `self` to `other`. Every key of either map is looked up once in the other one.
+    ///
+    /// The entries of each list come in no particular order.
+    pub fn diff<'a, S2: BuildHasher>(&'a self, other: &'a HashMap<K, V, S2>) -> MapDiff<'a, K, V> {
+        let mut diff = MapDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        for (key, value) in self {
+            match other.get(key) {
+                Some(new) if new != value => diff.changed.push((key, value, new)),
+                Some(_) => {}
+                None => diff.removed.push((key, value)),
+            }
+        }
+        diff.added = other
+            .into_iter()
+            .filter(|(key, _)| !self.contains_key(*key))
+            .collect();
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let mut desired = HashMap::new();
+        desired.extend(vec![("a", 1), ("b", 2), ("c", 3)]);
+        let mut actual = HashMap::new();
+        actual.extend(vec![("b", 2), ("c", 30), ("d", 4)]);
+
+        let diff = desired.diff(&actual);
+        assert_eq!(diff.added, vec![(&"d", &4)]);
+        assert_eq!(diff.removed, vec![(&"a", &1)]);
+        assert_eq!(diff.changed, vec![(&"c", &3, &30)]);
+        assert!(!diff.is_empty());
+        assert!(desired.diff(&desired).is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{make_hash, table, HashMap};
+
+/// The error returned by [`HashMap::try_from_iter`] when a key comes up twice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateKeyError<K, V> {
+    /// the second occurrence of the key.
+    pub key: K,
+    /// the value that came with the first occurrence of the key.
+    pub first: V,
+    /// the value that came with the second occurrence of the key.
+    pub second: V,
+}
+
+impl<K: fmt::Debug, V> fmt::Display for DuplicateKeyError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "duplicate key {:?}", self.key)
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> Error for DuplicateKeyError<K, V> {}
+
+impl<K, V> HashMap<K, V, RandomState>
+where
+    K: Hash + Eq,
+{
+    /// Collects the pairs of `iter` into a map, failing at the first key that is already in
+    /// there instead of overwriting its value.
+    pub fn try_from_iter<I>(iter: I) -> Result<Self, DuplicateKeyError<K, V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let iter = iter.into_iter();
+        let mut map = HashMap::with_capacity(iter.size_hint().0);
+        for (key, value) in iter {
+            let hash = make_hash(&map.hash_builder, &key);
+            let hash_builder = &map.hash_builder;
+            let (bucket, index) = map.table.slot(
+                hash,
+                |(ekey, _)| ekey == &key,
+                |(key, _)| make_hash(hash_builder, key),
+            );
+            match index {
+                Some(index) => {
+                    let (_, first) = map.table.remove_at(index).value;
+                    return Err(DuplicateKeyError {
+                        key,
+                        first,
+                        second: value,
+                    });
+                }
+                None => {
+                    map.table.insert_at(table::tag(hash), bucket, (key, value));
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_duplicates() {
+        let map = HashMap::try_from_iter(vec![("foo", 1), ("bar", 2)]).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("bar"), Some(&2));
+
+        let err = HashMap::try_from_iter(vec![("foo", 1), ("bar", 2), ("foo", 3)])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            DuplicateKeyError {
+                key: "foo",
+                first: 1,
+                second: 3
+            }
+        );
+        assert_eq!(err.to_string(), "duplicate key \"foo\"");
+    }
+}
@@ -0,0 +1,339 @@
+use std::mem;
+
+use crate::table::{HashTable, HashTag};
+
+/// A view into a single entry of a [`HashMap`](crate::HashMap), which may either be vacant or
+/// occupied.
+///
+/// Returned by [`HashMap::entry`](crate::HashMap::entry).
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry whose key is in the map.
+pub struct OccupiedEntry<'a, K, V> {
+    /// the key the entry was looked up with, if it was, for [`replace_key`](Self::replace_key).
+    key: Option<K>,
+    table: &'a mut HashTable<(K, V)>,
+    /// position of the entry in the entries of `table`.
+    index: usize,
+}
+
+/// An entry whose key is not in the map (yet).
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    /// tag of the hash of `key`.
+    tag: HashTag,
+    /// the bucket `key` belongs to, the map has already made sure it has room for one more item.
+    bucket: usize,
+    table: &'a mut HashTable<(K, V)>,
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    /// Returns the key of the entry: the one stored in the map if it is occupied, the one the
+    /// entry was looked up with otherwise.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting `default` if it is vacant, and returns a
+    /// mutable reference to the value.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Like [`or_insert`](Entry::or_insert), but only computes the value if it is needed.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Like [`or_insert_with`](Entry::or_insert_with), but the closure gets to see the key.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(&entry.key);
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Calls `f` with the value if the entry is occupied, before any potential inserts.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(ref mut entry) = self {
+            f(entry.get_mut());
+        }
+        self
+    }
+
+    /// If the entry is occupied, replaces or removes it based on what `f` returns, see
+    /// [`OccupiedEntry::replace_entry_with`]. Vacant entries are returned untouched.
+    pub fn and_replace_entry_with<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&K, V) -> Option<V>,
+    {
+        match self {
+            Entry::Occupied(entry) => entry.replace_entry_with(f),
+            Entry::Vacant(_) => self,
+        }
+    }
+}
+
+impl<'a, K, V: Default> Entry<'a, K, V> {
+    /// Ensures a value is in the entry by inserting the default value if it is vacant, and
+    /// returns a mutable reference to the value.
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub(crate) fn new(key: Option<K>, table: &'a mut HashTable<(K, V)>, index: usize) -> Self {
+        OccupiedEntry { key, table, index }
+    }
+
+    /// Returns the key stored in the map.
+    pub fn key(&self) -> &K {
+        &self.table.entries()[self.index].value.0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.table.entries()[self.index].value.1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.table.entries_mut()[self.index].value.1
+    }
+
+    /// Converts the entry into a mutable reference to the value, bound to the map's lifetime.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.table.entries_mut()[self.index].value.1
+    }
+
+    /// Sets the value of the entry, returning the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Takes the value out of the map.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Takes the key and the value out of the map.
+    pub fn remove_entry(self) -> (K, V) {
+        self.table.remove_at(self.index).value
+    }
+
+    /// Replaces both the key and the value of the entry by the key the entry was looked up with
+    /// and `value`, returning the old ones.
+    ///
+    /// # Panics
+    ///
+    /// If the entry wasn't returned by [`HashMap::entry`](crate::HashMap::entry), or its key has
+    /// been used already.
+    pub fn replace_entry(mut self, value: V) -> (K, V) {
+        let key = self.take_key();
+        let entry = &mut self.table.entries_mut()[self.index].value;
+        mem::replace(entry, (key, value))
+    }
+
+    /// Replaces the key stored in the map by the one the entry was looked up with, returning the
+    /// old one. The two are equal, but may not be the same: this swaps in a canonical instance.
+    ///
+    /// # Panics
+    ///
+    /// If the entry wasn't returned by [`HashMap::entry`](crate::HashMap::entry), or its key has
+    /// been used already.
+    pub fn replace_key(mut self) -> K {
+        let key = self.take_key();
+        mem::replace(&mut self.table.entries_mut()[self.index].value.0, key)
+    }
+
+    fn take_key(&mut self) -> K {
+        self.key
+            .take()
+            .expect("the entry has no lookup key to replace the stored one with")
+    }
+
+    /// Hands the key and the owned value to `f`, which decides what happens to the entry.
+    ///
+    /// If `f` returns `Some(value)`, `value` replaces the old one and the entry stays occupied.
+    /// If it returns `None` the entry is removed, and the returned vacant entry holds the key.
+    /// This makes e.g. reference counts that delete themselves when they hit zero a one-liner.
+    pub fn replace_entry_with<F>(self, f: F) -> Entry<'a, K, V>
+    where
+        F: FnOnce(&K, V) -> Option<V>,
+    {
+        let slot = self.table.remove_at(self.index);
+        let (tag, bucket, (key, value)) = (slot.tag, slot.bucket(), slot.value);
+        // if `f` panics the entry is gone already, and the table agrees.
+        match f(&key, value) {
+            Some(value) => {
+                let index = self.table.insert_at(tag, bucket, (key, value));
+                Entry::Occupied(OccupiedEntry::new(None, self.table, index))
+            }
+            None => Entry::Vacant(VacantEntry::new(key, tag, bucket, self.table)),
+        }
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub(crate) fn new(
+        key: K,
+        tag: HashTag,
+        bucket: usize,
+        table: &'a mut HashTable<(K, V)>,
+    ) -> Self {
+        VacantEntry {
+            key,
+            tag,
+            bucket,
+            table,
+        }
+    }
+
+    /// Returns the key the entry was looked up with.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes back the key, without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts the key with `value`, returning a mutable reference to the value.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.insert_entry(value).into_mut()
+    }
+
+    /// Inserts the key with `value`, returning the now occupied entry.
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, K, V> {
+        let index = self
+            .table
+            .insert_at(self.tag, self.bucket, (self.key, value));
+        OccupiedEntry::new(None, self.table, index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Entry, HashMap};
+    use std::rc::Rc;
+
+    #[test]
+    fn or_insert() {
+        let mut map = HashMap::new();
+        *map.entry("foo").or_insert(0) += 1;
+        *map.entry("foo").or_insert(0) += 1;
+        *map.entry("bar").or_insert_with(|| 10) += 1;
+        map.entry("baz").and_modify(|v| *v = 100).or_default();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get("foo"), Some(&2));
+        assert_eq!(map.get("bar"), Some(&11));
+        assert_eq!(map.get("baz"), Some(&0));
+    }
+
+    #[test]
+    fn occupied() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        match map.entry("foo") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.key(), &"foo");
+                assert_eq!(entry.insert(43), 42);
+                assert_eq!(entry.remove(), 43);
+            }
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn vacant() {
+        let mut map = HashMap::new();
+        match map.entry("foo".to_string()) {
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), "foo"),
+            Entry::Occupied(_) => unreachable!(),
+        }
+        assert!(map.is_empty());
+
+        let entry = map.entry("foo".to_string());
+        assert_eq!(entry.key(), "foo");
+        match entry {
+            Entry::Vacant(entry) => {
+                let mut entry = entry.insert_entry(1);
+                *entry.get_mut() += 1;
+                assert_eq!(entry.key(), "foo");
+            }
+            Entry::Occupied(_) => unreachable!(),
+        }
+        assert_eq!(map.get("foo"), Some(&2));
+        assert_eq!(map.entry("foo".to_string()).key(), "foo");
+    }
+
+    #[test]
+    fn replace_key() {
+        let mut map = HashMap::new();
+        let first = Rc::new("foo".to_string());
+        map.insert(first.clone(), 1);
+
+        let second = Rc::new("foo".to_string());
+        match map.entry(second.clone()) {
+            Entry::Occupied(entry) => assert!(Rc::ptr_eq(&entry.replace_key(), &first)),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert!(Rc::ptr_eq((&map).into_iter().next().unwrap().0, &second));
+
+        match map.entry(Rc::new("foo".to_string())) {
+            Entry::Occupied(entry) => {
+                let (key, value) = entry.replace_entry(2);
+                assert!(Rc::ptr_eq(&key, &second));
+                assert_eq!(value, 1);
+            }
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(map.get(&second), Some(&2));
+
+        match map.entry(second) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove_entry().1, 2),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn replace_entry_with() {
+        let mut map = HashMap::new();
+        map.insert("foo", 2);
+
+        // decrement the count, deleting the entry once it hits zero.
+        let release = |_: &&str, count: i32| if count > 1 { Some(count - 1) } else { None };
+        assert!(matches!(
+            map.entry("foo").and_replace_entry_with(release),
+            Entry::Occupied(_)
+        ));
+        assert_eq!(map.get("foo"), Some(&1));
+        assert!(matches!(
+            map.entry("foo").and_replace_entry_with(release),
+            Entry::Vacant(_)
+        ));
+        assert!(map.is_empty());
+        assert!(matches!(
+            map.entry("foo").and_replace_entry_with(release),
+            Entry::Vacant(_)
+        ));
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+//! A map over a small, dense key domain, where keys index an array directly.
+
+use std::iter::Enumerate;
+use std::marker::PhantomData;
+use std::ops::Index;
+use std::slice;
+
+/// A key type with a small number of values, each with its own index in `0..COUNT`.
+///
+/// Usually a field-less enum:
+///
+/// ```
+/// use rehash::EnumKey;
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// enum Opcode {
+///     Load,
+///     Store,
+///     Jump,
+/// }
+///
+/// impl EnumKey for Opcode {
+///     const COUNT: usize = 3;
+///
+///     fn index(&self) -> usize {
+///         *self as usize
+///     }
+///
+///     fn from_index(index: usize) -> Self {
+///         [Opcode::Load, Opcode::Store, Opcode::Jump][index]
+///     }
+/// }
+/// ```
+pub trait EnumKey: Sized {
+    /// Number of values of the type.
+    const COUNT: usize;
+
+    /// Returns the index of the value, below [`COUNT`](Self::COUNT). Different values must have
+    /// different indices.
+    fn index(&self) -> usize;
+
+    /// Returns the value whose index is `index`. Only called with indices of existing values.
+    fn from_index(index: usize) -> Self;
+}
+
+impl EnumKey for bool {
+    const COUNT: usize = 2;
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn from_index(index: usize) -> Self {
+        index != 0
+    }
+}
+
+impl EnumKey for u8 {
+    const COUNT: usize = 256;
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn from_index(index: usize) -> Self {
+        index as u8
+    }
+}
+
+/// A map whose values sit in an array with one slot per possible key, at the key's
+/// [`index`](EnumKey::index): nothing is hashed or compared, every operation is one array
+/// access.
+///
+/// The slots are allocated once, when the map is created, so the map takes room for
+/// [`EnumKey::COUNT`] values however few it holds. Iteration goes over the keys in index order,
+/// and gives them by value, as they aren't stored.
+pub struct EnumMap<K, V> {
+    values: Box<[Option<V>]>,
+    /// number of items in the map (for easy access)
+    items: usize,
+    keys: PhantomData<fn() -> K>,
+}
+
+impl<K: EnumKey, V> EnumMap<K, V> {
+    pub fn new() -> Self {
+        EnumMap {
+            values: (0..K::COUNT).map(|_| None).collect(),
+            items: 0,
+            keys: PhantomData,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Returns the number of items the map can hold, [`EnumKey::COUNT`].
+    pub fn capacity(&self) -> usize {
+        K::COUNT
+    }
+
+    /// Inserts a key-value pair into the map, returning the old value of the key if it was
+    /// there.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.values[key.index()].replace(value);
+        if old.is_none() {
+            self.items += 1;
+        }
+        old
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.values[key.index()].as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.values[key.index()].as_mut()
+    }
+
+    /// Returns a mutable reference to the value of `key`, inserting `default()` first if the
+    /// key isn't in the map.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V {
+        let slot = &mut self.values[key.index()];
+        if slot.is_none() {
+            self.items += 1;
+        }
+        slot.get_or_insert_with(default)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.values[key.index()].is_some()
+    }
+
+    /// Removes a key from the map, returning its value if it was there.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let old = self.values[key.index()].take();
+        if old.is_some() {
+            self.items -= 1;
+        }
+        old
+    }
+
+    /// Retains only the entries for which `f` returns true, removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(K, &mut V) -> bool,
+    {
+        for (index, slot) in self.values.iter_mut().enumerate() {
+            if let Some(value) = slot {
+                if !f(K::from_index(index), value) {
+                    *slot = None;
+                    self.items -= 1;
+                }
+            }
+        }
+    }
+
+    /// Removes all entries, keeping the slots.
+    pub fn clear(&mut self) {
+        for slot in self.values.iter_mut() {
+            *slot = None;
+        }
+        self.items = 0;
+    }
+
+    /// Iterates over the entries, in the order of the keys' indices.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            slots: self.values.iter().enumerate(),
+            keys: PhantomData,
+        }
+    }
+
+    /// Iterates over the entries with mutable references to the values, in the order of the
+    /// keys' indices.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            slots: self.values.iter_mut().enumerate(),
+            keys: PhantomData,
+        }
+    }
+}
+
+impl<K: EnumKey, V> Default for EnumMap<K, V> {
+    fn default() -> Self {
+        EnumMap::new()
+    }
+}
+
+impl<K, V: Clone> Clone for EnumMap<K, V> {
+    fn clone(&self) -> Self {
+        EnumMap {
+            values: self.values.clone(),
+            items: self.items,
+            keys: PhantomData,
+        }
+    }
+}
+
+impl<K: EnumKey, V> Index<K> for EnumMap<K, V> {
+    type Output = V;
+
+    /// Returns the value of `key`.
+    ///
+    /// # Panics
+    ///
+    /// If the key isn't in the map.
+    fn index(&self, key: K) -> &V {
+        self.get(&key).expect("key not in the map")
+    }
+}
+
+impl<K: EnumKey, V> Extend<(K, V)> for EnumMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    slots: Enumerate<slice::Iter<'a, Option<V>>>,
+    keys: PhantomData<fn() -> K>,
+}
+
+impl<'a, K: EnumKey, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots
+            .find_map(|(index, slot)| Some((K::from_index(index), slot.as_ref()?)))
+    }
+}
+
+pub struct IterMut<'a, K, V> {
+    slots: Enumerate<slice::IterMut<'a, Option<V>>>,
+    keys: PhantomData<fn() -> K>,
+}
+
+impl<'a, K: EnumKey, V> Iterator for IterMut<'a, K, V> {
+    type Item = (K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots
+            .find_map(|(index, slot)| Some((K::from_index(index), slot.as_mut()?)))
+    }
+}
+
+impl<'a, K: EnumKey, V> IntoIterator for &'a EnumMap<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum State {
+        Idle,
+        Running,
+        Done,
+    }
+
+    impl EnumKey for State {
+        const COUNT: usize = 3;
+
+        fn index(&self) -> usize {
+            *self as usize
+        }
+
+        fn from_index(index: usize) -> Self {
+            [State::Idle, State::Running, State::Done][index]
+        }
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = EnumMap::new();
+        assert_eq!(map.insert(State::Done, "done"), None);
+        assert_eq!(map.insert(State::Idle, "idle"), None);
+        assert_eq!(map.insert(State::Idle, "waiting"), Some("idle"));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[State::Idle], "waiting");
+        assert_eq!(map.get(&State::Running), None);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            vec![(State::Idle, &"waiting"), (State::Done, &"done")]
+        );
+
+        *map.get_or_insert_with(State::Running, || "") = "running";
+        map.retain(|state, _| state != State::Idle);
+        assert_eq!(map.remove(&State::Done), Some("done"));
+        assert_eq!(map.remove(&State::Done), None);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.capacity(), 3);
+
+        let mut bytes = EnumMap::<u8, usize>::new();
+        bytes.extend(b"hello".iter().map(|&b| (b, 0)));
+        for &b in b"hello" {
+            *bytes.get_mut(&b).unwrap() += 1;
+        }
+        assert_eq!(bytes[b'l'], 2);
+        assert_eq!(bytes.len(), 4);
+    }
+}
@@ -0,0 +1,24 @@
+use std::borrow::Borrow;
+
+/// Key equivalence used by lookups.
+///
+/// `map.get(q)` finds the entry whose key `k` satisfies `q.equivalent(k)`. Every `Q` that `K`
+/// can be borrowed as works out of the box, and custom key wrappers can implement this to allow
+/// cheap borrowed lookups `Borrow` can't express, like finding a `(String, String)` key with a
+/// pair of `&str`.
+///
+/// The implementation has to agree with `Hash`: if `q.equivalent(k)` then `q` and `k` must hash
+/// to the same value.
+pub trait Equivalent<K: ?Sized> {
+    fn equivalent(&self, key: &K) -> bool;
+}
+
+impl<Q, K> Equivalent<K> for Q
+where
+    Q: Eq + ?Sized,
+    K: Borrow<Q> + ?Sized,
+{
+    fn equivalent(&self, key: &K) -> bool {
+        self == key.borrow()
+    }
+}
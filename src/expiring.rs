@@ -0,0 +1,147 @@
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::{Equivalent, HashMap};
+
+/// A map whose entries expire a fixed amount of time after they were inserted.
+///
+/// Expired entries are ignored by lookups right away, but they keep their memory until they are
+/// overwritten, removed, or dropped by [`evict_expired`](ExpiringHashMap::evict_expired).
+pub struct ExpiringHashMap<K, V> {
+    /// value and the moment it expires, `None` if it never does.
+    map: HashMap<K, (V, Option<Instant>)>,
+    default_ttl: Duration,
+}
+
+impl<K, V> ExpiringHashMap<K, V> {
+    /// Creates an empty map where `insert` keeps the entries alive for `default_ttl`.
+    pub fn new(default_ttl: Duration) -> Self {
+        ExpiringHashMap {
+            map: HashMap::new(),
+            default_ttl,
+        }
+    }
+
+    pub fn default_ttl(&self) -> Duration {
+        self.default_ttl
+    }
+}
+
+impl<K, V> ExpiringHashMap<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Inserts a key-value pair that expires after the map's default TTL.
+    ///
+    /// Returns the previous value of the key, if it hadn't expired yet.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_with_ttl(key, value, self.default_ttl)
+    }
+
+    /// Inserts a key-value pair that expires after `ttl`.
+    ///
+    /// Returns the previous value of the key, if it hadn't expired yet.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let now = Instant::now();
+        // a TTL too long to represent simply never expires.
+        let deadline = now.checked_add(ttl);
+        self.map
+            .insert(key, (value, deadline))
+            .filter(|&(_, deadline)| alive(deadline, now))
+            .map(|(v, _)| v)
+    }
+
+    /// Returns a reference to the value corresponding to the key, unless it has expired.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let (value, deadline) = self.map.get(key)?;
+        if alive(*deadline, Instant::now()) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the key is in the map and hasn't expired.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning its value if it hadn't expired.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let (value, deadline) = self.map.remove(key)?;
+        if alive(deadline, Instant::now()) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Drops every expired entry, returning how many there were.
+    pub fn evict_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let before = self.map.len();
+        self.map.retain(|_, &mut (_, deadline)| alive(deadline, now));
+        before - self.map.len()
+    }
+
+    /// Returns the number of entries in the map, including expired ones that haven't been
+    /// evicted yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+fn alive(deadline: Option<Instant>, now: Instant) -> bool {
+    deadline.is_none_or(|deadline| now < deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn expired_entries_are_ignored() {
+        let mut map = ExpiringHashMap::new(HOUR);
+        map.insert("foo", 42);
+        map.insert_with_ttl("bar", 43, Duration::ZERO);
+        map.insert_with_ttl("baz", 44, Duration::MAX);
+
+        assert_eq!(map.get("foo"), Some(&42));
+        assert_eq!(map.get("bar"), None);
+        assert!(!map.contains_key("bar"));
+        assert_eq!(map.get("baz"), Some(&44));
+        assert_eq!(map.len(), 3);
+
+        // the expired value isn't handed back when it gets replaced.
+        assert_eq!(map.insert_with_ttl("bar", 45, Duration::ZERO), None);
+        assert_eq!(map.insert("foo", 46), Some(42));
+    }
+
+    #[test]
+    fn evict_expired() {
+        let mut map = ExpiringHashMap::new(Duration::ZERO);
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        map.insert_with_ttl(10, 10, HOUR);
+        assert_eq!(map.evict_expired(), 10);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.remove(&10), Some(10));
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::slice;
+
+use crate::table::{self, HashTable};
+use crate::{make_hash, Equivalent};
+
+/// A segment is split once it holds this many entries.
+const SEGMENT_SIZE: usize = 4096;
+
+struct Segment<K, V> {
+    /// number of leading hash bits all the keys of the segment share.
+    depth: u32,
+    table: HashTable<(K, V)>,
+}
+
+impl<K, V> Segment<K, V> {
+    fn new(depth: u32) -> Self {
+        Segment {
+            depth,
+            table: HashTable::new(),
+        }
+    }
+}
+
+/// A hash map made of many small tables, so that growing it never rehashes more than a few
+/// thousand entries at once (extendible hashing).
+///
+/// The leading bits of a key's hash pick an entry in a directory, which points to the segment
+/// holding the key. Several directory entries can point to the same segment. A full segment is
+/// split in two according to the next bit of its keys' hashes, and only the directory entries
+/// pointing to it change; when the segment was the only one behind its directory entry, the
+/// directory doubles first. That only copies pointers: the directory has (at most) two entries
+/// per segment.
+///
+/// So no insert moves more than `SEGMENT_SIZE` (4096) entries, whereas doubling a single huge
+/// table moves all of them at once.
+pub struct ExtendibleHashMap<K, V, S = RandomState> {
+    /// indexed by the leading `depth` bits of the hash.
+    directory: Vec<usize>,
+    depth: u32,
+    segments: Vec<Segment<K, V>>,
+    /// number of items in the hash-map (for easy access)
+    items: usize,
+    hash_builder: S,
+}
+
+impl<K, V> ExtendibleHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        ExtendibleHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> ExtendibleHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        ExtendibleHashMap {
+            directory: vec![0],
+            depth: 0,
+            segments: vec![Segment::new(0)],
+            items: 0,
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Returns the number of segments the map is split into.
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn segment(&self, hash: u64) -> usize {
+        match self.depth {
+            0 => self.directory[0],
+            depth => self.directory[(hash >> (u64::BITS - depth)) as usize],
+        }
+    }
+}
+
+impl<K, V, S: Default> Default for ExtendibleHashMap<K, V, S> {
+    fn default() -> Self {
+        ExtendibleHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> ExtendibleHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        let mut segment = self.segment(hash);
+        let table = &mut self.segments[segment].table;
+        if let Some((_, evalue)) = table.find_mut(hash, |(ekey, _)| ekey == &key) {
+            return Some(mem::replace(evalue, value));
+        }
+
+        while self.segments[segment].table.len() >= SEGMENT_SIZE && self.split(segment) {
+            segment = self.segment(hash);
+        }
+        let hash_builder = &self.hash_builder;
+        let hasher = |(key, _): &(K, V)| make_hash(hash_builder, key);
+        self.segments[segment]
+            .table
+            .insert_unique(hash, (key, value), hasher);
+        self.items += 1;
+        None
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        self.segments[self.segment(hash)]
+            .table
+            .find(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let segment = self.segment(hash);
+        self.segments[segment]
+            .table
+            .find_mut(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    ///
+    /// Segments are never merged back, an emptied segment keeps its place in the directory.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let segment = self.segment(hash);
+        let (_, value) = self.segments[segment]
+            .table
+            .remove(hash, |(ekey, _)| key.equivalent(ekey))?;
+        self.items -= 1;
+        Some(value)
+    }
+
+    /// Splits `segment` according to the next bit of its keys' hashes. Returns false if the
+    /// directory can't grow any more, and the segment has to go over its size instead.
+    fn split(&mut self, segment: usize) -> bool {
+        let depth = self.segments[segment].depth;
+        if depth == self.depth {
+            // keys piling into one segment (e.g. sharing their hash) mustn't double the
+            // directory over and over.
+            if self.depth == u64::BITS - 1 || self.directory.len() >= 2 * self.segments.len() {
+                return false;
+            }
+            self.directory = self.directory.iter().flat_map(|&s| [s, s]).collect();
+            self.depth += 1;
+        }
+
+        let hash_builder = &self.hash_builder;
+        let hasher = |(key, _): &(K, V)| make_hash(hash_builder, key);
+        let entries = mem::take(&mut self.segments[segment].table).into_hashed(hasher);
+        let bit = 1 << (u64::BITS - 1 - depth);
+        let mut low = Segment::new(depth + 1);
+        let mut high = Segment::new(depth + 1);
+        for (hash, entry) in entries {
+            let half = if hash & bit == 0 { &mut low } else { &mut high };
+            half.table.insert_unique(hash, entry, hasher);
+        }
+
+        // the directory entries of a segment are contiguous, the second half now goes to `high`.
+        let span = 1 << (self.depth - depth);
+        let start = self.directory.iter().position(|&s| s == segment).unwrap();
+        let new = self.segments.len();
+        for s in &mut self.directory[start + span / 2..start + span] {
+            *s = new;
+        }
+        self.segments[segment] = low;
+        self.segments.push(high);
+        true
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    segments: slice::Iter<'a, Segment<K, V>>,
+    entries: Option<table::Iter<'a, (K, V)>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.entries.as_mut().and_then(Iterator::next) {
+                Some((k, v)) => break Some((k, v)),
+                // no more items in the segment, move to next segment
+                None => self.entries = Some(self.segments.next()?.table.iter()),
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a ExtendibleHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            segments: self.segments.iter(),
+            entries: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_segments() {
+        let mut map = ExtendibleHashMap::new();
+        for i in 0..20_000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        assert_eq!(map.len(), 20_000);
+        assert!(map.segment_count() >= 20_000 / SEGMENT_SIZE);
+        assert!(map.segments.iter().all(|s| s.table.len() <= SEGMENT_SIZE));
+        for i in 0..20_000 {
+            assert_eq!(map.get(&i).copied(), Some(if i == 7 { 0 } else { i * 2 }));
+        }
+        assert_eq!(map.get(&20_000), None);
+
+        *map.get_mut(&7).unwrap() = 70;
+        assert_eq!(map.remove(&7), Some(70));
+        assert_eq!(map.remove(&7), None);
+        assert_eq!((&map).into_iter().count(), 19_999);
+    }
+
+    #[test]
+    fn directory_points_to_matching_segments() {
+        let mut map = ExtendibleHashMap::new();
+        for i in 0..50_000 {
+            map.insert(i, ());
+        }
+        assert_eq!(map.directory.len(), 1 << map.depth);
+        for (i, &segment) in map.directory.iter().enumerate() {
+            // every directory entry shares the segment's leading bits.
+            let depth = map.segments[segment].depth;
+            let prefix = i >> (map.depth - depth);
+            let span = 1 << (map.depth - depth);
+            assert!(map.directory[prefix * span..(prefix + 1) * span]
+                .iter()
+                .all(|&s| s == segment));
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! A C interface to a map of byte strings, behind the `ffi` feature.
+//!
+//! The map is only handed out as an opaque pointer, created by [`rehash_new`] and destroyed by
+//! [`rehash_free`]. Keys and values are copied in, so the caller keeps ownership of its buffers.
+//! A header can be generated with cbindgen, and the crate built as a `staticlib` or `cdylib`
+//! (e.g. `cargo rustc --release --features ffi --crate-type staticlib`).
+
+use std::ptr;
+use std::slice;
+
+use crate::HashMap;
+
+/// A map from byte strings to byte strings, only ever used through a pointer.
+pub struct RehashMap {
+    map: HashMap<Box<[u8]>, Box<[u8]>>,
+}
+
+/// Borrows `len` bytes at `data`, which may be null when `len` is 0.
+///
+/// SAFETY: unless `len` is 0, `data` has to point to `len` readable bytes that stay alive and
+/// unchanged for `'a`.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+}
+
+/// Creates an empty map. It has to be destroyed with [`rehash_free`].
+#[no_mangle]
+pub extern "C" fn rehash_new() -> *mut RehashMap {
+    Box::into_raw(Box::new(RehashMap {
+        map: HashMap::new(),
+    }))
+}
+
+/// Destroys a map created by [`rehash_new`], together with every key and value in it. Does
+/// nothing if `map` is null.
+///
+/// # Safety
+///
+/// `map` has to be null or come from [`rehash_new`], and must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn rehash_free(map: *mut RehashMap) {
+    if !map.is_null() {
+        drop(unsafe { Box::from_raw(map) });
+    }
+}
+
+/// Returns the number of entries in the map.
+///
+/// # Safety
+///
+/// `map` has to be a live map from [`rehash_new`].
+#[no_mangle]
+pub unsafe extern "C" fn rehash_len(map: *const RehashMap) -> usize {
+    unsafe { &*map }.map.len()
+}
+
+/// Copies the key and value into the map, replacing the value the key had. Returns true if the
+/// key was in the map already.
+///
+/// # Safety
+///
+/// `map` has to be a live map from [`rehash_new`]. `key` and `value` have to point to
+/// `key_len` and `value_len` readable bytes (or be null if their length is 0).
+#[no_mangle]
+pub unsafe extern "C" fn rehash_insert(
+    map: *mut RehashMap,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> bool {
+    let map = unsafe { &mut *map };
+    let key = unsafe { bytes(key, key_len) };
+    let value = unsafe { bytes(value, value_len) };
+    map.map.insert(key.into(), value.into()).is_some()
+}
+
+/// Looks up `key`. If it is in the map, stores the length of its value in `*value_len` and
+/// returns a pointer to the value; otherwise returns null and leaves `*value_len` alone.
+///
+/// The value stays owned by the map: the pointer is only valid until the map is changed or
+/// destroyed.
+///
+/// # Safety
+///
+/// `map` has to be a live map from [`rehash_new`], `key` has to point to `key_len` readable
+/// bytes (or be null if `key_len` is 0), and `value_len` has to be writable.
+#[no_mangle]
+pub unsafe extern "C" fn rehash_get(
+    map: *const RehashMap,
+    key: *const u8,
+    key_len: usize,
+    value_len: *mut usize,
+) -> *const u8 {
+    let map = unsafe { &*map };
+    let key = unsafe { bytes(key, key_len) };
+    match map.map.get(key) {
+        Some(value) => {
+            unsafe { *value_len = value.len() };
+            value.as_ptr()
+        }
+        None => ptr::null(),
+    }
+}
+
+/// Removes `key` and its value from the map. Returns true if the key was in the map.
+///
+/// # Safety
+///
+/// `map` has to be a live map from [`rehash_new`], and `key` has to point to `key_len`
+/// readable bytes (or be null if `key_len` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn rehash_remove(
+    map: *mut RehashMap,
+    key: *const u8,
+    key_len: usize,
+) -> bool {
+    let map = unsafe { &mut *map };
+    let key = unsafe { bytes(key, key_len) };
+    map.map.remove(key).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn through_the_handle() {
+        let map = rehash_new();
+        let (key, value) = (b"foo", b"bar");
+        unsafe {
+            assert!(!rehash_insert(map, key.as_ptr(), 3, value.as_ptr(), 3));
+            assert!(rehash_insert(map, key.as_ptr(), 3, value.as_ptr(), 2));
+            assert!(!rehash_insert(map, ptr::null(), 0, ptr::null(), 0));
+            assert_eq!(rehash_len(map), 2);
+
+            let mut len = 0;
+            let found = rehash_get(map, key.as_ptr(), 3, &mut len);
+            assert_eq!(slice::from_raw_parts(found, len), b"ba");
+            assert!(!rehash_get(map, ptr::null(), 0, &mut len).is_null());
+            assert_eq!(len, 0);
+            assert!(rehash_get(map, value.as_ptr(), 3, &mut len).is_null());
+
+            assert!(rehash_remove(map, key.as_ptr(), 3));
+            assert!(!rehash_remove(map, key.as_ptr(), 3));
+            assert_eq!(rehash_len(map), 1);
+            rehash_free(map);
+            rehash_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::hash_map::RandomState;
+use std::f64::consts::LN_2;
+use std::hash::{BuildHasher, Hash};
+
+use crate::{make_hash, Equivalent, HashMap};
+
+/// The filter is never sized for fewer keys than this.
+const MIN_CAPACITY: usize = 16;
+
+/// A map with a Bloom filter in front of it, for workloads where most lookups are misses.
+///
+/// Every key sets a few bits of the filter. A lookup checks the bits of its key first: if any
+/// of them is unset the key can't be in the map, and `None` comes back without touching a
+/// bucket. With `bits_per_key` bits of filter per key, only about `0.6^bits_per_key` of the
+/// misses (1% for 10 bits) get past the filter and have to search their bucket.
+///
+/// Bits can't be unset, so removed keys keep passing the filter. Once as many keys have been
+/// removed as are left, the filter is rebuilt from the remaining keys; it is also rebuilt, twice
+/// as big, when the map outgrows it.
+pub struct FilteredHashMap<K, V, S = RandomState> {
+    map: HashMap<K, V, S>,
+    filter: Bloom,
+    /// keys removed since the filter was last rebuilt, their bits are still set.
+    stale: usize,
+}
+
+struct Bloom {
+    bits: Vec<u64>,
+    /// number of bits set per key.
+    hashes: u32,
+    bits_per_key: usize,
+    /// number of keys the filter was sized for.
+    capacity: usize,
+}
+
+impl Bloom {
+    fn new(bits_per_key: usize, capacity: usize) -> Self {
+        let bits = (bits_per_key * capacity).max(64);
+        Bloom {
+            bits: vec![0; bits.div_ceil(64)],
+            // the number of hashes giving the fewest false positives.
+            hashes: ((bits_per_key as f64 * LN_2).round() as u32).clamp(1, 16),
+            bits_per_key,
+            capacity,
+        }
+    }
+
+    /// Bits of a key with hash `hash`, made from the two halves of the hash (double hashing).
+    fn positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let bits = self.bits.len() as u64 * 64;
+        let step = hash.rotate_left(32) | 1;
+        (0..self.hashes as u64)
+            .map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % bits) as usize)
+    }
+
+    fn insert(&mut self, hash: u64) {
+        for bit in self.positions(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns false if the key with hash `hash` is definitely not in the map.
+    fn may_contain(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+impl<K, V> FilteredHashMap<K, V, RandomState> {
+    /// Creates an empty map whose filter uses `bits_per_key` bits per key.
+    pub fn new(bits_per_key: usize) -> Self {
+        FilteredHashMap::with_hasher(bits_per_key, RandomState::new())
+    }
+}
+
+impl<K, V, S> FilteredHashMap<K, V, S> {
+    /// Creates an empty map whose filter uses `bits_per_key` bits per key, and which will use
+    /// `hash_builder` to hash keys.
+    pub fn with_hasher(bits_per_key: usize, hash_builder: S) -> Self {
+        FilteredHashMap {
+            map: HashMap::with_hasher(hash_builder),
+            filter: Bloom::new(bits_per_key, MIN_CAPACITY),
+            stale: 0,
+        }
+    }
+
+    pub fn bits_per_key(&self) -> usize {
+        self.filter.bits_per_key
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<K, V, S> FilteredHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.map.hash_builder, &key);
+        self.filter.insert(hash);
+        let old = self.map.insert_hashed(hash, key, value);
+        if self.map.len() + self.stale > self.filter.capacity {
+            self.rebuild_filter(2 * self.map.len());
+        }
+        old
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        if !self.filter.may_contain(hash) {
+            return None;
+        }
+        self.map
+            .table
+            .find(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        if !self.filter.may_contain(hash) {
+            return None;
+        }
+        let (_, value) = self
+            .map
+            .table
+            .remove(hash, |(ekey, _)| key.equivalent(ekey))?;
+        self.stale += 1;
+        if self.stale > self.map.len() {
+            self.rebuild_filter(2 * self.map.len());
+        }
+        Some(value)
+    }
+
+    /// Replaces the filter by one sized for `capacity` keys, holding only the current keys.
+    fn rebuild_filter(&mut self, capacity: usize) {
+        let mut filter = Bloom::new(self.filter.bits_per_key, capacity.max(MIN_CAPACITY));
+        for (key, _) in &self.map {
+            filter.insert(make_hash(&self.map.hash_builder, key));
+        }
+        self.filter = filter;
+        self.stale = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = FilteredHashMap::new(10);
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get(&42), Some(&84));
+        assert_eq!(map.get(&1000), None);
+
+        for i in 0..600 {
+            assert_eq!(map.remove(&i), Some(if i == 7 { 0 } else { i * 2 }));
+        }
+        assert_eq!(map.remove(&0), None);
+        // more than half of the keys were removed, the filter was rebuilt from the rest.
+        assert!(map.stale < map.len());
+        assert_eq!(map.get(&999), Some(&1998));
+    }
+
+    #[test]
+    fn filter_rejects_most_misses() {
+        let mut map = FilteredHashMap::new(10);
+        for i in 0..10_000 {
+            map.insert(i, ());
+        }
+        let hash_builder = &map.map.hash_builder;
+        let passed = (10_000..20_000)
+            .filter(|i| map.filter.may_contain(make_hash(hash_builder, i)))
+            .count();
+        // about 1% is expected with 10 bits per key.
+        assert!(passed < 500, "{} misses got past the filter", passed);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::slice;
+
+use crate::{make_hash, Equivalent};
+
+type Slot<K, V> = (u64, K, V);
+
+/// A map holding at most `N` entries, all stored inline: it never allocates.
+///
+/// Entries live in an array of `N` slots using linear probing. A key's search starts at the
+/// slot picked by its hash and walks forward until it finds the key or an empty slot. Removals
+/// shift the following entries back instead of leaving tombstones, so lookups never get slower
+/// than the current entries make them.
+///
+/// Inserting a new key into a full map fails and hands the pair back. The map can be filled up
+/// completely, but lookups of missing keys in a (nearly) full map have to probe most of the
+/// slots: leave some room when they matter.
+///
+/// Nothing here needs an allocator, but the crate as a whole still builds against `std`, so
+/// it can't be used on `no_std` targets yet.
+pub struct FixedHashMap<K, V, const N: usize, S = RandomState> {
+    slots: [Option<Slot<K, V>>; N],
+    /// number of items in the hash-map (for easy access)
+    items: usize,
+    hash_builder: S,
+}
+
+impl<K, V, const N: usize> FixedHashMap<K, V, N, RandomState> {
+    pub fn new() -> Self {
+        FixedHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, const N: usize, S> FixedHashMap<K, V, N, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        FixedHashMap {
+            slots: std::array::from_fn(|_| None),
+            items: 0,
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Returns the number of items the map can hold, `N`.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.items == N
+    }
+
+    fn home(hash: u64) -> usize {
+        (hash % N as u64) as usize
+    }
+
+    /// Returns the slot of the entry with hash `hash` for which `eq` returns true, or the empty
+    /// slot its search stopped at.
+    fn probe<F>(&self, hash: u64, mut eq: F) -> Result<usize, Option<usize>>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        if N == 0 {
+            return Err(None);
+        }
+        let home = Self::home(hash);
+        for i in (home..N).chain(0..home) {
+            match &self.slots[i] {
+                Some((ehash, ekey, _)) if *ehash == hash && eq(ekey) => return Ok(i),
+                Some(_) => {}
+                None => return Err(Some(i)),
+            }
+        }
+        Err(None)
+    }
+
+    /// Empties slot `i`, moving back the entries after it that would otherwise be cut off from
+    /// their home slot.
+    fn remove_slot(&mut self, mut i: usize) -> Slot<K, V> {
+        let removed = self.slots[i].take().unwrap();
+        self.items -= 1;
+        let mut j = i;
+        loop {
+            j = (j + 1) % N;
+            let home = match &self.slots[j] {
+                Some((hash, _, _)) => Self::home(*hash),
+                None => break,
+            };
+            // the entry in `j` can move to the hole in `i` unless its home lies in `(i, j]`,
+            // (cyclically), where its search would start past the hole.
+            let reachable = if i <= j {
+                i < home && home <= j
+            } else {
+                i < home || home <= j
+            };
+            if !reachable {
+                self.slots[i] = self.slots[j].take();
+                i = j;
+            }
+        }
+        removed
+    }
+}
+
+impl<K, V, const N: usize, S: Default> Default for FixedHashMap<K, V, N, S> {
+    fn default() -> Self {
+        FixedHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, const N: usize, S> FixedHashMap<K, V, N, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `Ok(None)` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned. If the key is new but the
+    /// map is full, the key and value are given back in `Err`.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        let hash = make_hash(&self.hash_builder, &key);
+        match self.probe(hash, |ekey| ekey == &key) {
+            Ok(i) => {
+                let (_, _, evalue) = self.slots[i].as_mut().unwrap();
+                Ok(Some(mem::replace(evalue, value)))
+            }
+            Err(Some(i)) => {
+                self.slots[i] = Some((hash, key, value));
+                self.items += 1;
+                Ok(None)
+            }
+            Err(None) => Err((key, value)),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let i = self.probe(hash, |ekey| key.equivalent(ekey)).ok()?;
+        self.slots[i].as_ref().map(|(_, _, v)| v)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let i = self.probe(hash, |ekey| key.equivalent(ekey)).ok()?;
+        self.slots[i].as_mut().map(|(_, _, v)| v)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let i = self.probe(hash, |ekey| key.equivalent(ekey)).ok()?;
+        Some(self.remove_slot(i).2)
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    slots: slice::Iter<'a, Option<Slot<K, V>>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots.by_ref().flatten().next().map(|(_, k, v)| (k, v))
+    }
+}
+
+impl<'a, K, V, const N: usize, S> IntoIterator for &'a FixedHashMap<K, V, N, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            slots: self.slots.iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::IdentityBuildHasher;
+
+    #[test]
+    fn fills_up() {
+        let mut map = FixedHashMap::<_, _, 8>::new();
+        for i in 0..8 {
+            assert_eq!(map.insert(i, i * 10), Ok(None));
+        }
+        assert!(map.is_full());
+        assert_eq!(map.insert(3, 31), Ok(Some(30)));
+        assert_eq!(map.insert(8, 80), Err((8, 80)));
+        assert_eq!(map.get(&3), Some(&31));
+        assert_eq!(map.get(&8), None);
+
+        assert_eq!(map.remove(&0), Some(0));
+        assert_eq!(map.insert(8, 80), Ok(None));
+        assert_eq!((&map).into_iter().count(), 8);
+    }
+
+    #[test]
+    fn removal_keeps_probe_chains() {
+        // the keys are their own hash: 1, 9 and 17 all start at slot 1, 2 right after them.
+        let mut map = FixedHashMap::<_, _, 8, _>::with_hasher(IdentityBuildHasher::<u64>::new());
+        for key in [1, 9, 2, 17, 7, 15] {
+            assert_eq!(map.insert(key, key), Ok(None));
+        }
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.remove(&7), Some(7));
+        for key in [9, 2, 17, 15] {
+            assert_eq!(map.get(&key), Some(&key));
+        }
+        assert_eq!(map.len(), 4);
+        assert!(FixedHashMap::<u64, u64, 0>::new().insert(1, 1).is_err());
+    }
+}
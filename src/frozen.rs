@@ -0,0 +1,329 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::slice;
+
+use crate::table::{tag, HashTag};
+use crate::{make_hash, Equivalent, HashMap};
+
+/// An immutable map built once from a [`HashMap`] by [`freeze`](HashMap::freeze), laid out for
+/// lookups.
+///
+/// All the entries sit in a single allocation sorted by bucket, with no spare capacity, and a
+/// bucket is just a range of it: a lookup hashes the key, reads two offsets and scans the few
+/// entries in between. There is no way to mutate the map, so sharing it between threads only
+/// needs an `Arc` (it is `Sync` whenever `K`, `V` and `S` are).
+///
+/// [`freeze_perfect`](HashMap::freeze_perfect) goes one step further and computes a perfect hash
+/// function for the keys, so that a lookup only ever looks at a single entry.
+pub struct FrozenHashMap<K, V, S = RandomState> {
+    index: Index,
+    entries: Box<[(HashTag, K, V)]>,
+    hash_builder: S,
+}
+
+/// How to find the entries of a key.
+enum Index {
+    /// bucket `i` is `entries[offsets[i]..offsets[i + 1]]`.
+    Chained(Box<[usize]>),
+    /// a key whose hash falls in group `g` is at `displaced_slot(hash, displacements[g], _)`.
+    Perfect(Box<[u32]>),
+}
+
+/// Average number of keys per group of the perfect hash function. Bigger groups make it smaller,
+/// but slower to build.
+const GROUP_SIZE: usize = 4;
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Turns the map into a read-only [`FrozenHashMap`] holding the same entries.
+    pub fn freeze(self) -> FrozenHashMap<K, V, S> {
+        let hash_builder = self.hash_builder;
+        let hashed = self
+            .table
+            .into_hashed(|(key, _)| make_hash(&hash_builder, key));
+        FrozenHashMap::chained(hashed, hash_builder)
+    }
+
+    /// Like [`freeze`](HashMap::freeze), but also computes a minimal perfect hash function over
+    /// the keys: every key gets a slot of its own, so a lookup compares against exactly one
+    /// entry. Made for static dictionaries such as keyword tables.
+    ///
+    /// This uses hash and displace (CHD): keys are split into small groups by hash, and each
+    /// group, biggest first, gets the first displacement value that sends all of its keys to
+    /// free slots. Lookups then read one displacement and one entry. Building takes a few
+    /// passes over the keys, far more work than [`freeze`](HashMap::freeze).
+    ///
+    /// Keys with exactly the same 64-bit hash can't be told apart by any function of the hash;
+    /// in that (very unlikely) case the map is frozen the regular way, which
+    /// [`is_perfect`](FrozenHashMap::is_perfect) reports.
+    pub fn freeze_perfect(self) -> FrozenHashMap<K, V, S> {
+        let hash_builder = self.hash_builder;
+        let hashed = self
+            .table
+            .into_hashed(|(key, _)| make_hash(&hash_builder, key));
+        match displacements(&hashed) {
+            Some((displacements, slots)) => {
+                let mut entries: Vec<_> = (0..hashed.len()).map(|_| None).collect();
+                for ((hash, (key, value)), slot) in hashed.into_iter().zip(slots) {
+                    entries[slot] = Some((tag(hash), key, value));
+                }
+                FrozenHashMap {
+                    index: Index::Perfect(displacements.into_boxed_slice()),
+                    entries: entries.into_iter().map(Option::unwrap).collect(),
+                    hash_builder,
+                }
+            }
+            None => FrozenHashMap::chained(hashed, hash_builder),
+        }
+    }
+}
+
+impl<K, V, S> FrozenHashMap<K, V, S> {
+    fn chained(mut hashed: Vec<(u64, (K, V))>, hash_builder: S) -> Self {
+        // one entry per bucket on average, without load factor headroom: nothing is ever added.
+        let buckets = hashed.len().max(1);
+        let bucket = |hash: u64| (hash % buckets as u64) as usize;
+        hashed.sort_unstable_by_key(|&(hash, _)| bucket(hash));
+
+        let mut offsets = vec![0; buckets + 1];
+        for &(hash, _) in &hashed {
+            offsets[bucket(hash) + 1] += 1;
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
+
+        FrozenHashMap {
+            index: Index::Chained(offsets.into_boxed_slice()),
+            entries: hashed
+                .into_iter()
+                .map(|(hash, (key, value))| (tag(hash), key, value))
+                .collect(),
+            hash_builder,
+        }
+    }
+
+    /// Returns true if the map was built with a perfect hash function.
+    pub fn is_perfect(&self) -> bool {
+        matches!(self.index, Index::Perfect(_))
+    }
+
+    /// Returns a reference to the map's hasher builder.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Returns the number of items in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K, V, S> FrozenHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let entries = match self.index {
+            Index::Chained(ref offsets) => {
+                let bucket = (hash % (offsets.len() - 1) as u64) as usize;
+                &self.entries[offsets[bucket]..offsets[bucket + 1]]
+            }
+            Index::Perfect(ref displacements) => {
+                if self.entries.is_empty() {
+                    return None;
+                }
+                let group = (hash % displacements.len() as u64) as usize;
+                let slot = displaced_slot(hash, displacements[group], self.entries.len());
+                slice::from_ref(&self.entries[slot])
+            }
+        };
+        let tag = tag(hash);
+        entries
+            .iter()
+            .find(|(etag, ekey, _)| *etag == tag && key.equivalent(ekey))
+            .map(|(_, _, v)| v)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+/// Computes a displacement per group of keys such that all of the `hashed` entries end up in
+/// different slots, and returns them along with the slot of every entry. Returns `None` if some
+/// keys can't be separated.
+fn displacements<T>(hashed: &[(u64, T)]) -> Option<(Vec<u32>, Vec<usize>)> {
+    let len = hashed.len();
+    let mut hashes: Vec<u64> = hashed.iter().map(|&(hash, _)| hash).collect();
+    hashes.sort_unstable();
+    if hashes.windows(2).any(|pair| pair[0] == pair[1]) {
+        return None;
+    }
+
+    let mut groups = vec![Vec::new(); len.div_ceil(GROUP_SIZE).max(1)];
+    for (i, &(hash, _)) in hashed.iter().enumerate() {
+        let group = (hash % groups.len() as u64) as usize;
+        groups[group].push(i);
+    }
+    // the biggest groups are the hardest to place, do them while most slots are still free.
+    let mut order: Vec<usize> = (0..groups.len()).collect();
+    order.sort_unstable_by_key(|&group| Reverse(groups[group].len()));
+
+    let mut displacements = vec![0; groups.len()];
+    let mut slots = vec![0; len];
+    let mut taken = vec![false; len];
+    let mut candidate = Vec::new();
+    for group in order {
+        let keys = &groups[group];
+        let fits = |displacement: u32, candidate: &mut Vec<usize>| {
+            candidate.clear();
+            for &i in keys {
+                let slot = displaced_slot(hashed[i].0, displacement, len);
+                if taken[slot] || candidate.contains(&slot) {
+                    return false;
+                }
+                candidate.push(slot);
+            }
+            true
+        };
+        displacements[group] = (0..=u32::MAX).find(|&d| fits(d, &mut candidate))?;
+        for (&i, &slot) in keys.iter().zip(&candidate) {
+            taken[slot] = true;
+            slots[i] = slot;
+        }
+    }
+    Some((displacements, slots))
+}
+
+/// The slot of a key with hash `hash` in a group displaced by `displacement`, out of `len`.
+fn displaced_slot(hash: u64, displacement: u32, len: usize) -> usize {
+    // the group was picked by `hash % groups`, mix every bit in so the slot doesn't depend on it.
+    let mut x = hash ^ (displacement as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    (x % len as u64) as usize
+}
+
+pub struct Iter<'a, K, V> {
+    entries: slice::Iter<'a, (HashTag, K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|(_, k, v)| (k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a FrozenHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            entries: self.entries.iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn freeze() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+        let frozen = map.freeze();
+        assert_eq!(frozen.len(), 100);
+        assert_eq!(frozen.get("42"), Some(&42));
+        assert_eq!(frozen.get("100"), None);
+        assert!(frozen.contains_key("0"));
+        assert_eq!((&frozen).into_iter().map(|(_, v)| v).sum::<i32>(), 4950);
+
+        let empty = HashMap::<u32, u32>::new().freeze();
+        assert!(empty.is_empty());
+        assert_eq!(empty.get(&0), None);
+    }
+
+    #[test]
+    fn freeze_perfect() {
+        let keywords = ["fn", "let", "mut", "if", "else", "match", "loop", "while", "for", "in"];
+        let mut map = HashMap::new();
+        for (i, &keyword) in keywords.iter().enumerate() {
+            map.insert(keyword, i);
+        }
+        let frozen = map.freeze_perfect();
+        assert!(frozen.is_perfect());
+        for (i, keyword) in keywords.iter().enumerate() {
+            assert_eq!(frozen.get(keyword), Some(&i));
+        }
+        assert_eq!(frozen.get("struct"), None);
+
+        let empty = HashMap::<u32, u32>::new().freeze_perfect();
+        assert_eq!(empty.get(&0), None);
+    }
+
+    #[test]
+    fn perfect_falls_back_on_equal_hashes() {
+        #[derive(Default)]
+        struct Constant;
+        impl std::hash::Hasher for Constant {
+            fn finish(&self) -> u64 {
+                7
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut map = HashMap::with_hasher(std::hash::BuildHasherDefault::<Constant>::default());
+        map.insert(1, "one");
+        map.insert(2, "two");
+        let frozen = map.freeze_perfect();
+        assert!(!frozen.is_perfect());
+        assert_eq!(frozen.get(&2), Some(&"two"));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        let frozen = Arc::new(map.freeze());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = Arc::clone(&frozen);
+                thread::spawn(move || *frozen.get("foo").unwrap())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Hasher builders to plug into [`HashMap`](crate::HashMap) in place of the default
+//! [`RandomState`](std::collections::hash_map::RandomState).
+
+use std::any::TypeId;
+#[cfg(feature = "fxhash")]
+use std::convert::TryInto;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+#[cfg(any(feature = "fxhash", feature = "fnv"))]
+use std::hash::BuildHasherDefault;
+use std::hash::{BuildHasher, Hasher};
+use std::marker::PhantomData;
+
+use crate::HashMap;
+
+/// A map keyed by integers that are used as their own hash.
+pub type IdentityHashMap<K, V> = HashMap<K, V, IdentityBuildHasher<K>>;
+
+/// A map using the fastest general purpose hasher enabled through the crate's features: FxHash
+/// (`fxhash` feature), otherwise FNV-1a (`fnv` feature).
+///
+/// These hashers are much faster than SipHash on small keys, but they have no seed, so keys
+/// chosen by an attacker can all be sent to the same bucket. Only use this for keys you trust.
+#[cfg(feature = "fxhash")]
+pub type FastHashMap<K, V> = HashMap<K, V, FxBuildHasher>;
+#[cfg(all(feature = "fnv", not(feature = "fxhash")))]
+pub type FastHashMap<K, V> = HashMap<K, V, FnvBuildHasher>;
+
+#[cfg(feature = "fxhash")]
+pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+#[cfg(feature = "fnv")]
+pub type FnvBuildHasher = BuildHasherDefault<FnvHasher>;
+
+/// The hash used by rustc: a rotate, xor and multiply per word of input.
+#[cfg(feature = "fxhash")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+#[cfg(feature = "fxhash")]
+impl FxHasher {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(Self::SEED);
+    }
+}
+
+#[cfg(feature = "fxhash")]
+impl Hasher for FxHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.add_to_hash(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        for &byte in chunks.remainder() {
+            self.add_to_hash(byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add_to_hash(n as u64);
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.add_to_hash(n as u64);
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.add_to_hash(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add_to_hash(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add_to_hash(n as u64);
+    }
+}
+
+/// 64-bit FNV-1a: an xor and multiply per byte of input.
+///
+/// Simple and good on short keys, slower than FxHash on long ones.
+#[cfg(feature = "fnv")]
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher {
+    hash: u64,
+}
+
+#[cfg(feature = "fnv")]
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+#[cfg(feature = "fnv")]
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// A hasher builder with an explicit seed, for maps that need to behave the same way on every run.
+///
+/// Two maps built with the same seed hash every key to the same value, so the same sequence of
+/// operations gives the same bucket layout and the same iteration order, run after run. That
+/// holds for a given build of the program: the underlying algorithm and the `Hash` impls of std
+/// types may change between Rust releases. With the `randomize-iteration` feature only the
+/// layout is reproducible, every iteration still starts from a random bucket.
+///
+/// This is not DoS-resistant. Anyone who knows (or guesses) the seed can craft keys that all land
+/// in the same bucket, so don't use it for keys coming from untrusted input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SeedableState {
+    seed: u64,
+}
+
+impl SeedableState {
+    pub const fn with_seed(seed: u64) -> Self {
+        SeedableState { seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl BuildHasher for SeedableState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        // `DefaultHasher::new` always starts from the same keys, mixing the seed in first makes
+        // every seed hash differently.
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
+
+/// Key types that [`IdentityHasher`] can hash: primitive integers (and `TypeId`), which hash by
+/// writing a single integer exactly once.
+pub trait IdentityHashable {}
+
+macro_rules! identity_hashable {
+    ($($t:ty),*) => {
+        $(impl IdentityHashable for $t {})*
+    };
+}
+
+identity_hashable!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+// hashes as the lower 64 bits of its id.
+impl IdentityHashable for TypeId {}
+
+/// A hasher that returns the integer it was given as the hash, without doing any work.
+///
+/// Running small integer IDs through SipHash is pure overhead: they already are spread evenly
+/// over the buckets (as long as they don't all share the same low bits, e.g. all being
+/// multiples of 1024). It offers no protection at all against keys chosen by an attacker.
+///
+/// Only integers can be hashed; anything calling `write` with raw bytes (strings, slices, ...)
+/// makes it panic. [`IdentityBuildHasher`] only accepts [`IdentityHashable`] keys, which rules
+/// that out at compile time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdentityHasher {
+    hash: u64,
+}
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        panic!("IdentityHasher can only hash integers");
+    }
+
+    // the signed variants forward to these by default.
+    fn write_u8(&mut self, n: u8) {
+        self.hash = n as u64;
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.hash = n as u64;
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.hash = n as u64;
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.hash = n;
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.hash = n as u64;
+    }
+}
+
+/// Builds [`IdentityHasher`]s for keys of type `K`.
+pub struct IdentityBuildHasher<K> {
+    // `fn() -> K` so that the builder is always `Send`, `Sync` and `Copy`.
+    key: PhantomData<fn() -> K>,
+}
+
+impl<K: IdentityHashable> IdentityBuildHasher<K> {
+    pub const fn new() -> Self {
+        IdentityBuildHasher { key: PhantomData }
+    }
+}
+
+impl<K: IdentityHashable> BuildHasher for IdentityBuildHasher<K> {
+    type Hasher = IdentityHasher;
+
+    fn build_hasher(&self) -> IdentityHasher {
+        IdentityHasher::default()
+    }
+}
+
+// implemented by hand, derives would put the bounds on `K`.
+impl<K: IdentityHashable> Default for IdentityBuildHasher<K> {
+    fn default() -> Self {
+        IdentityBuildHasher::new()
+    }
+}
+
+impl<K> Clone for IdentityBuildHasher<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for IdentityBuildHasher<K> {}
+
+impl<K> fmt::Debug for IdentityBuildHasher<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IdentityBuildHasher")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_hash_to_themselves() {
+        let build = IdentityBuildHasher::<u32>::new();
+        assert_eq!(build.hash_one(42u32), 42);
+        assert_eq!(IdentityBuildHasher::<i64>::new().hash_one(-1i64), u64::MAX);
+        assert_eq!(IdentityBuildHasher::<usize>::new().hash_one(7usize), 7);
+    }
+
+    #[test]
+    fn identity_map() {
+        let mut map = IdentityHashMap::default();
+        for i in 0..100u64 {
+            map.insert(i, i * 2);
+        }
+        assert_eq!(map.get(&42), Some(&84));
+        // consecutive ids fill the buckets perfectly evenly.
+        assert_eq!(map.stats().max_chain, 1);
+    }
+
+    #[test]
+    fn seeded_maps_are_reproducible() {
+        let build = || {
+            let mut map = HashMap::with_hasher(SeedableState::with_seed(42));
+            for i in 0..100 {
+                map.insert(i.to_string(), i);
+            }
+            map
+        };
+        let (a, b) = (build(), build());
+        assert!(a.table.buckets() == b.table.buckets());
+        #[cfg(not(feature = "randomize-iteration"))]
+        assert!((&a).into_iter().eq(&b));
+
+        let other = SeedableState::with_seed(43);
+        assert_ne!(a.hasher().hash_one("foo"), other.hash_one("foo"));
+    }
+
+    #[cfg(feature = "fxhash")]
+    #[test]
+    fn fxhash() {
+        let build = FxBuildHasher::default();
+        assert_eq!(build.hash_one(1u64), 0x51_7c_c1_b7_27_22_0a_95);
+        assert_ne!(build.hash_one("foo"), build.hash_one("bar"));
+
+        let mut map = FastHashMap::default();
+        map.insert("foo", 42);
+        assert_eq!(map.get("foo"), Some(&42));
+    }
+
+    #[cfg(feature = "fnv")]
+    #[test]
+    fn fnv() {
+        // reference values of 64-bit FNV-1a.
+        let mut hasher = FnvHasher::default();
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let mut map = HashMap::with_hasher(FnvBuildHasher::default());
+        map.insert("foo", 42);
+        assert_eq!(map.get("foo"), Some(&42));
+    }
+
+    #[test]
+    #[should_panic(expected = "only hash integers")]
+    fn bytes_are_rejected() {
+        IdentityHasher::default().write(b"foo");
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::slice;
+
+use crate::{make_hash, Equivalent};
+
+/// Every entry is at most `NEIGHBORHOOD - 1` slots away from its home slot.
+const NEIGHBORHOOD: usize = 32;
+/// Number of slots allocated by the first insert.
+const INITIAL_SLOTS: usize = NEIGHBORHOOD;
+/// An insert looks this far (at most) for a free slot before growing the table instead.
+const MAX_PROBE: usize = 512;
+
+type Slot<K, V> = (u64, K, V);
+
+/// A hash map using hopscotch hashing: every entry stays within a small neighborhood of its
+/// home slot.
+///
+/// The home slot of a key is picked by its hash, and the entry is stored in one of the
+/// `NEIGHBORHOOD` (32) slots starting there. Each slot has a bitmap of which of those slots hold
+/// its entries, so a lookup reads one bitmap and only compares the entries it points at: probes
+/// are bounded by the neighborhood, however full the table gets. An insert takes the closest
+/// free slot; if that's too far from home, entries between the two "hop" towards it, each one
+/// staying within its own neighborhood, until the free slot is close enough. When no entry can
+/// make room the table doubles.
+///
+/// Entries that can't get a neighborhood slot even in a mostly empty table (only possible when
+/// dozens of keys have the same hash) go to an overflow list that lookups search too.
+pub struct HopscotchHashMap<K, V, S = RandomState> {
+    slots: Vec<Option<Slot<K, V>>>,
+    /// bit `d` of `hops[i]` is set when slot `i + d` holds an entry whose home is `i`.
+    hops: Vec<u32>,
+    overflow: Vec<Slot<K, V>>,
+    /// number of items in the hash-map (for easy access)
+    items: usize,
+    hash_builder: S,
+}
+
+impl<K, V> HopscotchHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        HopscotchHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> HopscotchHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        HopscotchHashMap {
+            // allocation happens during initial insert.
+            slots: Vec::new(),
+            hops: Vec::new(),
+            overflow: Vec::new(),
+            items: 0,
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    fn home(&self, hash: u64) -> usize {
+        (hash % self.slots.len() as u64) as usize
+    }
+
+    /// Returns the slot of the entry with hash `hash` for which `eq` returns true.
+    fn find_slot<F>(&self, hash: u64, mut eq: F) -> Option<usize>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let home = self.home(hash);
+        let mut hops = self.hops[home];
+        while hops != 0 {
+            let slot = (home + hops.trailing_zeros() as usize) % self.slots.len();
+            hops &= hops - 1;
+            if matches!(self.slots[slot], Some((ehash, ref ekey, _)) if ehash == hash && eq(ekey)) {
+                return Some(slot);
+            }
+        }
+        None
+    }
+
+    fn find_overflow<F>(&self, hash: u64, mut eq: F) -> Option<usize>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.overflow
+            .iter()
+            .position(|(ehash, ekey, _)| *ehash == hash && eq(ekey))
+    }
+
+    /// Puts `entry` in the table, growing it if it doesn't fit.
+    fn place(&mut self, mut entry: Slot<K, V>) {
+        loop {
+            entry = match self.try_place(entry) {
+                Ok(()) => return,
+                Err(homeless) => homeless,
+            };
+            // a mostly empty table that still can't fit the entry won't do better when bigger:
+            // too many keys share the same hash.
+            if self.slots.len() > 8 * self.items {
+                self.overflow.push(entry);
+                return;
+            }
+            self.grow();
+        }
+    }
+
+    /// Puts `entry` in the neighborhood of its home slot, making entries hop out of the way.
+    /// Gives the entry back if there's no room.
+    fn try_place(&mut self, entry: Slot<K, V>) -> Result<(), Slot<K, V>> {
+        let len = self.slots.len();
+        let home = self.home(entry.0);
+        // distance from `home` to the closest free slot.
+        let mut free =
+            match (0..len.min(MAX_PROBE)).find(|d| self.slots[(home + d) % len].is_none()) {
+                Some(free) => free,
+                None => return Err(entry),
+            };
+
+        while free >= NEIGHBORHOOD {
+            let free_slot = (home + free) % len;
+            // the farthest home slot with an entry between itself and `free_slot`, which can
+            // therefore move there without leaving its neighborhood.
+            let hop = (1..NEIGHBORHOOD).rev().find_map(|back| {
+                let from_home = (free_slot + len - back) % len;
+                let movable = self.hops[from_home] & ((1 << back) - 1);
+                (movable != 0).then(|| (from_home, movable.trailing_zeros() as usize, back))
+            });
+            let (from_home, offset, back) = match hop {
+                Some(hop) => hop,
+                None => return Err(entry),
+            };
+            let from = (from_home + offset) % len;
+            self.slots[free_slot] = self.slots[from].take();
+            self.hops[from_home] &= !(1 << offset);
+            self.hops[from_home] |= 1 << back;
+            free -= back - offset;
+        }
+
+        self.slots[(home + free) % len] = Some(entry);
+        self.hops[home] |= 1 << free;
+        Ok(())
+    }
+
+    fn grow(&mut self) {
+        let target_size = match self.slots.len() {
+            0 => INITIAL_SLOTS,
+            n => 2 * n,
+        };
+        let old_slots = mem::replace(&mut self.slots, (0..target_size).map(|_| None).collect());
+        self.hops = vec![0; target_size];
+        let old_overflow = mem::take(&mut self.overflow);
+        // every entry keeps its hash, nothing needs hashing again.
+        for entry in old_slots.into_iter().flatten().chain(old_overflow) {
+            self.place(entry);
+        }
+    }
+}
+
+impl<K, V, S: Default> Default for HopscotchHashMap<K, V, S> {
+    fn default() -> Self {
+        HopscotchHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> HopscotchHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        if let Some(evalue) = self.find_mut(hash, |ekey| ekey == &key) {
+            return Some(mem::replace(evalue, value));
+        }
+
+        // past 7/8 full, free slots get too far apart for hopping to be cheap.
+        if 8 * (self.items + 1) > 7 * self.slots.len() {
+            self.grow();
+        }
+        self.items += 1;
+        self.place((hash, key, value));
+        None
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let eq = |ekey: &K| key.equivalent(ekey);
+        if let Some(slot) = self.find_slot(hash, eq) {
+            return self.slots[slot].as_ref().map(|(_, _, v)| v);
+        }
+        self.find_overflow(hash, eq).map(|i| &self.overflow[i].2)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        self.find_mut(hash, |ekey| key.equivalent(ekey))
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let eq = |ekey: &K| key.equivalent(ekey);
+        if let Some(slot) = self.find_slot(hash, eq) {
+            let home = self.home(hash);
+            let distance = (slot + self.slots.len() - home) % self.slots.len();
+            self.hops[home] &= !(1 << distance);
+            self.items -= 1;
+            return self.slots[slot].take().map(|(_, _, v)| v);
+        }
+        let i = self.find_overflow(hash, eq)?;
+        self.items -= 1;
+        Some(self.overflow.swap_remove(i).2)
+    }
+
+    fn find_mut<F>(&mut self, hash: u64, mut eq: F) -> Option<&mut V>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        if let Some(slot) = self.find_slot(hash, &mut eq) {
+            return self.slots[slot].as_mut().map(|(_, _, v)| v);
+        }
+        let i = self.find_overflow(hash, eq)?;
+        Some(&mut self.overflow[i].2)
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    slots: slice::Iter<'a, Option<Slot<K, V>>>,
+    overflow: slice::Iter<'a, Slot<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots
+            .by_ref()
+            .flatten()
+            .chain(&mut self.overflow)
+            .next()
+            .map(|(_, k, v)| (k, v))
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a HopscotchHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            slots: self.slots.iter(),
+            overflow: self.overflow.iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = HopscotchHashMap::new();
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i).copied(), Some(if i == 7 { 0 } else { i * 2 }));
+        }
+        assert_eq!(map.get(&1000), None);
+
+        *map.get_mut(&7).unwrap() = 70;
+        assert_eq!(map.remove(&7), Some(70));
+        assert_eq!(map.remove(&7), None);
+        assert_eq!(map.len(), 999);
+        assert_eq!((&map).into_iter().count(), 999);
+        assert!(map.overflow.is_empty());
+    }
+
+    #[test]
+    fn entries_stay_in_their_neighborhood() {
+        let mut map = HopscotchHashMap::new();
+        for i in 0..5000 {
+            map.insert(i, ());
+        }
+        let len = map.slots.len();
+        for (slot, entry) in map.slots.iter().enumerate() {
+            if let Some((hash, _, _)) = entry {
+                let home = map.home(*hash);
+                let distance = (slot + len - home) % len;
+                assert!(distance < NEIGHBORHOOD);
+                assert_ne!(map.hops[home] & (1 << distance), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn equal_hashes_overflow() {
+        #[derive(Default)]
+        struct Constant;
+        impl std::hash::Hasher for Constant {
+            fn finish(&self) -> u64 {
+                7
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let hash_builder = std::hash::BuildHasherDefault::<Constant>::default();
+        let mut map = HopscotchHashMap::with_hasher(hash_builder);
+        for i in 0..40 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.overflow.len(), 40 - NEIGHBORHOOD);
+        for i in 0..40 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        assert_eq!(map.remove(&39), Some(39));
+        assert_eq!(map.get(&39), None);
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use crate::{make_hash, table, Equivalent, HashMap};
+
+/// Default probe length from which [`Observer::long_probe`] is called.
+const DEFAULT_LONG_PROBE: usize = 8;
+
+/// Receives the events of an [`InstrumentedHashMap`].
+///
+/// Every method does nothing by default, so an observer only implements what it cares about.
+/// They take `&self` because lookups report long probes too: keep counters in atomics or cells.
+pub trait Observer {
+    /// The table is about to move its entries from `old_buckets` to `new_buckets` buckets.
+    fn resize_start(&self, old_buckets: usize, new_buckets: usize) {
+        let _ = (old_buckets, new_buckets);
+    }
+
+    /// The table is done moving its entries from `old_buckets` to `new_buckets` buckets.
+    fn resize_end(&self, old_buckets: usize, new_buckets: usize) {
+        let _ = (old_buckets, new_buckets);
+    }
+
+    /// An operation had to search a chain of `probe_len` entries, which is at least the long
+    /// probe threshold of the map.
+    fn long_probe(&self, probe_len: usize) {
+        let _ = probe_len;
+    }
+}
+
+/// A map reporting its resizes and long chains to an [`Observer`], to find out why an
+/// operation was slow instead of guessing.
+///
+/// Every insert, lookup and removal looks at the length of the chain it searches; chains of at
+/// least [`long_probe`](InstrumentedHashMap::long_probe) entries are reported. Resizes are
+/// reported right before and after the entries move, with the bucket counts on both sides.
+pub struct InstrumentedHashMap<K, V, O, S = RandomState> {
+    map: HashMap<K, V, S>,
+    observer: O,
+    long_probe: usize,
+}
+
+impl<K, V, O: Observer> InstrumentedHashMap<K, V, O, RandomState> {
+    /// Creates an empty map reporting to `observer`.
+    pub fn new(observer: O) -> Self {
+        InstrumentedHashMap::with_hasher(observer, RandomState::new())
+    }
+}
+
+impl<K, V, O: Observer, S> InstrumentedHashMap<K, V, O, S> {
+    /// Creates an empty map reporting to `observer`, which will use `hash_builder` to hash keys.
+    pub fn with_hasher(observer: O, hash_builder: S) -> Self {
+        InstrumentedHashMap {
+            map: HashMap::with_hasher(hash_builder),
+            observer,
+            long_probe: DEFAULT_LONG_PROBE,
+        }
+    }
+
+    /// Reports the chains of at least `long_probe` entries from now on (8 by default).
+    pub fn set_long_probe(&mut self, long_probe: usize) {
+        self.long_probe = long_probe;
+    }
+
+    /// Returns the probe length from which the observer is told about a chain.
+    pub fn long_probe(&self) -> usize {
+        self.long_probe
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of buckets the map currently has.
+    pub fn bucket_count(&self) -> usize {
+        self.map.bucket_count()
+    }
+
+    /// Gives back the map and the observer.
+    pub fn into_parts(self) -> (HashMap<K, V, S>, O) {
+        (self.map, self.observer)
+    }
+
+    /// Tells the observer about the chain a key with hash `hash` has to search.
+    fn probe(&self, hash: u64) {
+        let probe_len = self.map.table.chain_len(hash);
+        if probe_len >= self.long_probe {
+            self.observer.long_probe(probe_len);
+        }
+    }
+}
+
+impl<K, V, O, S> InstrumentedHashMap<K, V, O, S>
+where
+    K: Hash + Eq,
+    O: Observer,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.map.hash_builder, &key);
+        self.probe(hash);
+        if let Some((_, evalue)) = self.map.table.find_mut(hash, |(ekey, _)| ekey == &key) {
+            return Some(mem::replace(evalue, value));
+        }
+        self.grow(1);
+        let hash_builder = &self.map.hash_builder;
+        let hasher = |(key, _): &(K, V)| make_hash(hash_builder, key);
+        self.map.table.insert_unique(hash, (key, value), hasher);
+        None
+    }
+
+    /// Reserves room for at least `additional` more items, so that they can be inserted with at
+    /// most one resize (the one happening here).
+    pub fn reserve(&mut self, additional: usize) {
+        self.grow(additional);
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        self.probe(hash);
+        self.map
+            .table
+            .find(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        self.probe(hash);
+        self.map
+            .table
+            .remove(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Makes room for `additional` more items, telling the observer if the table has to grow.
+    fn grow(&mut self, additional: usize) {
+        let old_buckets = self.map.bucket_count();
+        let new_buckets = table::buckets_for(self.map.len() + additional);
+        if additional == 0 || new_buckets <= old_buckets {
+            return;
+        }
+        self.observer.resize_start(old_buckets, new_buckets);
+        self.map.reserve(additional);
+        self.observer.resize_end(old_buckets, new_buckets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    use crate::hash::IdentityBuildHasher;
+
+    #[derive(Default)]
+    struct Events(RefCell<Vec<String>>);
+
+    impl Observer for Events {
+        fn resize_start(&self, old_buckets: usize, new_buckets: usize) {
+            self.0
+                .borrow_mut()
+                .push(format!("start {} -> {}", old_buckets, new_buckets));
+        }
+
+        fn resize_end(&self, old_buckets: usize, new_buckets: usize) {
+            self.0
+                .borrow_mut()
+                .push(format!("end {} -> {}", old_buckets, new_buckets));
+        }
+
+        fn long_probe(&self, probe_len: usize) {
+            self.0.borrow_mut().push(format!("probe {}", probe_len));
+        }
+    }
+
+    #[test]
+    fn reports_resizes() {
+        let mut map = InstrumentedHashMap::new(Events::default());
+        for i in 0..3 {
+            map.insert(i, i);
+        }
+        map.insert(0, 10);
+        assert_eq!(map.get(&0), Some(&10));
+        assert_eq!(
+            *map.observer().0.borrow(),
+            [
+                "start 0 -> 1",
+                "end 0 -> 1",
+                "start 1 -> 2",
+                "end 1 -> 2",
+                "start 2 -> 4",
+                "end 2 -> 4"
+            ]
+        );
+        assert_eq!(map.bucket_count(), 4);
+    }
+
+    #[test]
+    fn reports_long_probes() {
+        // every key is a multiple of the bucket count, so they all share bucket 0.
+        let mut map =
+            InstrumentedHashMap::with_hasher(Events::default(), IdentityBuildHasher::<u64>::new());
+        map.set_long_probe(4);
+        map.reserve(16);
+        let buckets = map.bucket_count() as u64;
+        for i in 0..5 {
+            map.insert(i * buckets, ());
+        }
+        map.observer().0.borrow_mut().clear();
+
+        assert!(map.contains_key(&0));
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.remove(&buckets), Some(()));
+        assert_eq!(*map.observer().0.borrow(), ["probe 5", "probe 5"]);
+    }
+}
@@ -0,0 +1,174 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+
+use crate::{make_hash, Equivalent, HashTable};
+
+/// A handle to a value stored in an [`Interner`].
+///
+/// Two symbols from the same interner are equal exactly when the values they stand for are, so
+/// comparing (or hashing) them is as cheap as comparing two `u32`s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Returns the position of the value in the interner: symbols are handed out as 0, 1, 2...
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Deduplicates values, handing out a [`Symbol`] for every distinct one.
+///
+/// Values are appended to an arena (a `Vec` that is never shrunk, so symbols stay valid for as
+/// long as the interner lives), and the table only holds symbols: looking up a value hashes it
+/// once, then compares it with the arena entries its bucket points to.
+///
+/// ```
+/// use rehash::Interner;
+///
+/// let mut interner: Interner = Interner::new();
+/// let foo = interner.intern("foo");
+/// assert_eq!(interner.intern("foo"), foo);
+/// assert_ne!(interner.intern("bar"), foo);
+/// assert_eq!(interner.resolve(foo), "foo");
+/// ```
+pub struct Interner<T = String, S = RandomState> {
+    values: Vec<T>,
+    table: HashTable<Symbol>,
+    hash_builder: S,
+}
+
+impl<T> Interner<T, RandomState> {
+    pub fn new() -> Self {
+        Interner::with_hasher(RandomState::new())
+    }
+}
+
+impl<T, S> Interner<T, S> {
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Interner {
+            values: Vec::new(),
+            table: HashTable::new(),
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of distinct values interned.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the value `symbol` stands for.
+    ///
+    /// # Panics
+    ///
+    /// If `symbol` comes from another interner holding more values than this one.
+    pub fn resolve(&self, symbol: Symbol) -> &T {
+        &self.values[symbol.index()]
+    }
+
+    /// Iterates over the symbols and their values, in the order they were interned.
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &T)> {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (Symbol(index as u32), value))
+    }
+}
+
+impl<T, S> Interner<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Returns the symbol of `value`, if it has been interned.
+    pub fn get<Q>(&self, value: &Q) -> Option<Symbol>
+    where
+        Q: Hash + Equivalent<T> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, value);
+        let values = &self.values;
+        self.table
+            .find(hash, |symbol| value.equivalent(&values[symbol.index()]))
+            .copied()
+    }
+
+    /// Returns the symbol of `value`, storing an owned copy of it first if it hasn't been
+    /// interned yet.
+    ///
+    /// # Panics
+    ///
+    /// If the interner would hold more than `u32::MAX` values.
+    pub fn intern<Q>(&mut self, value: &Q) -> Symbol
+    where
+        Q: Hash + Equivalent<T> + ToOwned + ?Sized,
+        Q::Owned: Into<T>,
+    {
+        match self.get(value) {
+            Some(symbol) => symbol,
+            None => self.push(make_hash(&self.hash_builder, value), value.to_owned().into()),
+        }
+    }
+
+    /// Like [`intern`](Self::intern), but takes the value by ownership, so it is not copied
+    /// when it is new.
+    pub fn intern_owned(&mut self, value: T) -> Symbol {
+        match self.get(&value) {
+            Some(symbol) => symbol,
+            None => self.push(make_hash(&self.hash_builder, &value), value),
+        }
+    }
+
+    fn push(&mut self, hash: u64, value: T) -> Symbol {
+        let symbol = Symbol(u32::try_from(self.values.len()).expect("too many interned values"));
+        self.values.push(value);
+        let (values, hash_builder) = (&self.values, &self.hash_builder);
+        self.table.insert_unique(hash, symbol, |symbol| {
+            make_hash(hash_builder, &values[symbol.index()])
+        });
+        symbol
+    }
+}
+
+impl<T, S: Default> Default for Interner<T, S> {
+    fn default() -> Self {
+        Interner::with_hasher(S::default())
+    }
+}
+
+impl<T: Borrow<str>, S> Interner<T, S> {
+    /// Returns the string `symbol` stands for, see [`resolve`](Self::resolve).
+    pub fn resolve_str(&self, symbol: Symbol) -> &str {
+        self.resolve(symbol).borrow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deduplicates() {
+        let mut interner: Interner = Interner::new();
+        let symbols: Vec<_> = (0..100)
+            .map(|i| interner.intern(&*format!("word{}", i % 10)))
+            .collect();
+        assert_eq!(interner.len(), 10);
+        for (i, symbol) in symbols.iter().enumerate() {
+            assert_eq!(*symbol, symbols[i % 10]);
+            assert_eq!(interner.resolve_str(*symbol), format!("word{}", i % 10));
+        }
+
+        assert_eq!(interner.get("word3"), Some(symbols[3]));
+        assert_eq!(interner.get("word10"), None);
+        assert_eq!(interner.intern_owned("word3".to_string()), symbols[3]);
+        let order: Vec<_> = interner.iter().map(|(symbol, _)| symbol.index()).collect();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use crate::{Equivalent, HashMap, Iter};
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Iterates over the keys of `self` that are also in `other`.
+    pub fn keys_intersection<'a, V2, S2: BuildHasher>(
+        &'a self,
+        other: &'a HashMap<K, V2, S2>,
+    ) -> KeysIntersection<'a, K, V, V2, S2> {
+        KeysIntersection {
+            iter: self.into_iter(),
+            other,
+        }
+    }
+
+    /// Iterates over the keys of `self` that are not in `other`.
+    pub fn keys_difference<'a, V2, S2: BuildHasher>(
+        &'a self,
+        other: &'a HashMap<K, V2, S2>,
+    ) -> KeysDifference<'a, K, V, V2, S2> {
+        KeysDifference {
+            iter: self.into_iter(),
+            other,
+        }
+    }
+
+    /// Joins `self` and `other` by key: yields the key and both values for every key that is in
+    /// both maps.
+    pub fn iter_matching<'a, V2, S2: BuildHasher>(
+        &'a self,
+        other: &'a HashMap<K, V2, S2>,
+    ) -> Matching<'a, K, V, V2, S2> {
+        Matching {
+            iter: self.into_iter(),
+            other,
+        }
+    }
+
+    /// Returns a view of the keys of the map as a set, to compare with the keys of other maps
+    /// without collecting them anywhere.
+    pub fn keys_view(&self) -> KeysView<'_, K, V, S> {
+        KeysView { map: self }
+    }
+}
+
+/// The keys of a map seen as a set, see [`HashMap::keys_view`].
+pub struct KeysView<'a, K, V, S = RandomState> {
+    map: &'a HashMap<K, V, S>,
+}
+
+// derives would require K, V and S to be `Clone`.
+impl<K, V, S> Clone for KeysView<'_, K, V, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V, S> Copy for KeysView<'_, K, V, S> {}
+
+impl<'a, K, V, S> KeysView<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Keys<'a, K, V> {
+        Keys {
+            iter: self.map.into_iter(),
+        }
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns true if no key is in both views. Looks the keys of the smaller view up in the
+    /// bigger one.
+    pub fn is_disjoint<V2, S2: BuildHasher>(&self, other: &KeysView<'_, K, V2, S2>) -> bool {
+        if self.len() <= other.len() {
+            self.iter().all(|key| !other.contains(key))
+        } else {
+            other.iter().all(|key| !self.contains(key))
+        }
+    }
+
+    /// Returns true if every key of `self` is in `other`.
+    pub fn is_subset<V2, S2: BuildHasher>(&self, other: &KeysView<'_, K, V2, S2>) -> bool {
+        self.len() <= other.len() && self.iter().all(|key| other.contains(key))
+    }
+
+    /// Returns true if every key of `other` is in `self`.
+    pub fn is_superset<V2, S2: BuildHasher>(&self, other: &KeysView<'_, K, V2, S2>) -> bool {
+        other.is_subset(self)
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for KeysView<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a K;
+    type IntoIter = Keys<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Keys<'a, K, V> {
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(k, _)| k)
+    }
+}
+
+pub struct KeysIntersection<'a, K, V, V2, S2 = RandomState> {
+    iter: Iter<'a, K, V>,
+    other: &'a HashMap<K, V2, S2>,
+}
+
+impl<'a, K, V, V2, S2> Iterator for KeysIntersection<'a, K, V, V2, S2>
+where
+    K: Hash + Eq,
+    S2: BuildHasher,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter
+            .by_ref()
+            .map(|(k, _)| k)
+            .find(|k| other.contains_key(*k))
+    }
+}
+
+pub struct KeysDifference<'a, K, V, V2, S2 = RandomState> {
+    iter: Iter<'a, K, V>,
+    other: &'a HashMap<K, V2, S2>,
+}
+
+impl<'a, K, V, V2, S2> Iterator for KeysDifference<'a, K, V, V2, S2>
+where
+    K: Hash + Eq,
+    S2: BuildHasher,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter
+            .by_ref()
+            .map(|(k, _)| k)
+            .find(|k| !other.contains_key(*k))
+    }
+}
+
+pub struct Matching<'a, K, V, V2, S2 = RandomState> {
+    iter: Iter<'a, K, V>,
+    other: &'a HashMap<K, V2, S2>,
+}
+
+impl<'a, K, V, V2, S2> Iterator for Matching<'a, K, V, V2, S2>
+where
+    K: Hash + Eq,
+    S2: BuildHasher,
+{
+    type Item = (&'a K, &'a V, &'a V2);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter
+            .by_ref()
+            .find_map(|(k, v)| other.get(k).map(|v2| (k, v, v2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maps() -> (HashMap<&'static str, i32>, HashMap<&'static str, String>) {
+        let mut left = HashMap::new();
+        left.insert("foo", 1);
+        left.insert("bar", 2);
+        left.insert("baz", 3);
+        let mut right = HashMap::new();
+        right.insert("bar", "two".to_string());
+        right.insert("baz", "three".to_string());
+        right.insert("quo", "four".to_string());
+        (left, right)
+    }
+
+    #[test]
+    fn intersection_and_difference() {
+        let (left, right) = maps();
+        let mut both: Vec<_> = left.keys_intersection(&right).copied().collect();
+        both.sort();
+        assert_eq!(both, ["bar", "baz"]);
+
+        let only_left: Vec<_> = left.keys_difference(&right).copied().collect();
+        assert_eq!(only_left, ["foo"]);
+        let only_right: Vec<_> = right.keys_difference(&left).copied().collect();
+        assert_eq!(only_right, ["quo"]);
+    }
+
+    #[test]
+    fn keys_view() {
+        let (left, right) = maps();
+        let (left, right) = (left.keys_view(), right.keys_view());
+        assert!(left.contains("foo"));
+        assert!(!left.is_disjoint(&right));
+        assert!(!left.is_subset(&right));
+
+        let mut small = HashMap::new();
+        small.insert("baz", ());
+        let small = small.keys_view();
+        assert!(small.is_subset(&left) && small.is_subset(&right));
+        assert!(right.is_superset(&small));
+
+        let mut other = HashMap::new();
+        other.insert("qux", 0);
+        assert!(other.keys_view().is_disjoint(&left));
+        assert_eq!(small.into_iter().collect::<Vec<_>>(), [&"baz"]);
+    }
+
+    #[test]
+    fn iter_matching() {
+        let (left, right) = maps();
+        let mut joined: Vec<_> = left
+            .iter_matching(&right)
+            .map(|(&k, &v, v2)| (k, v, v2.as_str()))
+            .collect();
+        joined.sort();
+        assert_eq!(joined, [("bar", 2, "two"), ("baz", 3, "three")]);
+    }
+}
@@ -1,44 +1,268 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::cmp::Ordering;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::mem;
-use std::borrow::Borrow;
+
+mod adaptive;
+mod any;
+#[cfg(feature = "async")]
+pub mod async_concurrent;
+mod builder;
+pub mod cache;
+mod chain;
+pub mod concurrent;
+mod convert;
+pub mod cow;
+pub mod cuckoo;
+mod diff;
+mod duplicate;
+mod entry;
+pub mod enum_map;
+mod equivalent;
+mod expiring;
+pub mod extendible;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filtered;
+pub mod fixed;
+pub mod frozen;
+pub mod hash;
+pub mod hopscotch;
+mod instrumented;
+mod intern;
+mod join;
+mod macros;
+pub mod observable;
+pub mod persist;
+pub mod persistent;
+pub mod set;
+pub mod small;
+pub mod soa;
+pub mod stable;
+mod stats;
+pub mod table;
+mod transaction;
+pub mod versioned;
+mod weak;
+
+pub use adaptive::{AdaptiveHashMap, AdaptiveHasher, AdaptiveState};
+pub use any::AnyMap;
+#[cfg(feature = "async")]
+pub use async_concurrent::AsyncConcurrentHashMap;
+pub use builder::HashMapBuilder;
+pub use cache::Cache;
+pub use concurrent::ConcurrentHashMap;
+pub use cow::CowHashMap;
+pub use cuckoo::CuckooHashMap;
+pub use diff::MapDiff;
+pub use duplicate::DuplicateKeyError;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use enum_map::{EnumKey, EnumMap};
+pub use equivalent::Equivalent;
+pub use expiring::ExpiringHashMap;
+pub use extendible::ExtendibleHashMap;
+pub use filtered::FilteredHashMap;
+pub use fixed::FixedHashMap;
+pub use frozen::FrozenHashMap;
+#[cfg(any(feature = "fxhash", feature = "fnv"))]
+pub use hash::FastHashMap;
+pub use hash::{IdentityHashMap, SeedableState};
+pub use hopscotch::HopscotchHashMap;
+pub use instrumented::{InstrumentedHashMap, Observer};
+pub use intern::{Interner, Symbol};
+pub use join::{Keys, KeysDifference, KeysIntersection, KeysView, Matching};
+pub use observable::ObservableHashMap;
+pub use set::HashSet;
+pub use small::SmallHashMap;
+pub use soa::SoaHashMap;
+pub use stable::StableHashMap;
+pub use stats::{Distribution, Stats};
+pub use table::HashTable;
+pub use transaction::Transaction;
+pub use versioned::VersionedHashMap;
+pub use weak::WeakValueHashMap;
 
 const INITIAL_BUCKETS: usize = 1;
 
-pub struct HashMap<K, V> {
-    buckets: Vec<Vec<(K, V)>>,
-    /// number of items in the hash-map (for easy access)
-    items: usize,
+/// A hash map implemented with separate chaining, on top of a [`HashTable`] of `(K, V)` pairs.
+///
+/// Entries are stored as `(hash, (K, V))` tuples, so a zero-sized value type costs nothing: a
+/// `HashMap<K, ()>` keeps exactly one `K` (and its hash) per entry, the same footprint a
+/// dedicated set would have.
+///
+/// Keys are hashed with `S`, which defaults to the randomly seeded [`RandomState`], so that the
+/// bucket of a key can't be predicted from the outside.
+pub struct HashMap<K, V, S = RandomState> {
+    table: HashTable<(K, V)>,
+    hash_builder: S,
 }
 
-impl<K, V> HashMap<K, V> {
+impl<K, V> HashMap<K, V, RandomState> {
     pub fn new() -> Self {
-        HashMap {
-            // allocation happens during initial insert.
-            buckets: Vec::new(),
-            items: 0,
-        }
+        HashMap::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty map with enough buckets to hold at least `capacity` items without
+    /// resizing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashMap::with_capacity_and_hasher(capacity, RandomState::new())
     }
 }
 
-impl<K, V> HashMap<K, V>
+impl<K, V> HashMap<K, Vec<V>, RandomState>
 where
     K: Hash + Eq,
 {
-    /// We need K and Q to have implementations of the Hash and Eq traits that produce identical results
-    fn bucket<Q>(&self, key: &Q) -> Option<usize>
+    /// Collects the pairs of `iter` into a map from every key to all of its values, in the order
+    /// they came in.
+    pub fn from_iter_grouped<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut map = HashMap::new();
+        for (key, value) in iter {
+            map.entry(key).or_insert_with(Vec::new).push(value);
+        }
+        map
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    ///
+    /// This is a `const fn` and doesn't allocate, so with a hasher builder that can be made in a
+    /// constant (no [`RandomState`], its seed is drawn at runtime) a map can live in a static:
+    ///
+    /// ```
+    /// use std::sync::Mutex;
+    /// use rehash::{HashMap, SeedableState};
+    ///
+    /// static CONFIG: Mutex<HashMap<&str, u32, SeedableState>> =
+    ///     Mutex::new(HashMap::with_hasher(SeedableState::with_seed(0)));
+    ///
+    /// CONFIG.lock().unwrap().insert("port", 8080);
+    /// assert_eq!(CONFIG.lock().unwrap().get("port"), Some(&8080));
+    /// ```
+    pub const fn with_hasher(hash_builder: S) -> Self {
+        HashMap {
+            table: HashTable::new(),
+            hash_builder,
+        }
+    }
+
+    /// Creates an empty map with enough buckets to hold at least `capacity` items without
+    /// resizing, which will use `hash_builder` to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        HashMap {
+            table: HashTable::with_capacity(capacity),
+            hash_builder,
+        }
+    }
+
+    /// Returns a reference to the map's hasher builder.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Returns the number of buckets the map currently has.
+    pub fn bucket_count(&self) -> usize {
+        self.table.bucket_count()
+    }
+
+    /// Returns the average number of entries per bucket the map grows past, 3/4 unless set
+    /// otherwise through [`HashMap::builder`].
+    pub fn max_load_factor(&self) -> f32 {
+        self.table.max_load()
+    }
+
+    /// Returns the number of bytes the map has allocated on the heap: the bucket table plus the
+    /// capacity of every bucket.
+    ///
+    /// Memory owned by the keys and values themselves (e.g. the contents of a `String`) is not
+    /// included.
+    pub fn allocated_bytes(&self) -> usize {
+        self.table.allocated_bytes()
+    }
+
+    /// Turns every value into `f(value)`.
+    ///
+    /// The keys don't change, so neither does the layout of the table: no key is hashed again.
+    pub fn map_values<U, F>(self, mut f: F) -> HashMap<K, U, S>
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized,
+        F: FnMut(V) -> U,
     {
-        if self.buckets.is_empty() {
-            return None;
+        HashMap {
+            table: self.table.map(|(key, value)| (key, f(value))),
+            hash_builder: self.hash_builder,
         }
-        // need to create a new hasher everytime for a fresh hash value.
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        // TODO: Implement something better than modulo
-        Some((hasher.finish() % self.buckets.len() as u64) as usize)
+    }
+
+    /// Turns every key into `f(key)`, building the table again for the new keys.
+    ///
+    /// If `f` maps two keys to the same new key, only one of their entries is kept (which one is
+    /// unspecified).
+    pub fn map_keys<K2, F>(self, mut f: F) -> HashMap<K2, V, S>
+    where
+        K2: Hash + Eq,
+        S: BuildHasher,
+        F: FnMut(K) -> K2,
+    {
+        let mut map = HashMap::with_hasher(self.hash_builder);
+        map.insert_batch(self.table.into_iter().map(|(key, value)| (f(key), value)));
+        map
+    }
+
+    /// Iterates over the entries in the order given by `compare`, e.g.
+    /// `map.iter_sorted_by(|a, b| a.1.cmp(b.1))` to sort them by value.
+    ///
+    /// The entries are collected and sorted up front, which takes `O(n log n)` time and `O(n)`
+    /// memory. Entries `compare` finds equal come out in an unspecified order.
+    pub fn iter_sorted_by<F>(&self, mut compare: F) -> std::vec::IntoIter<(&K, &V)>
+    where
+        F: FnMut(&(&K, &V), &(&K, &V)) -> Ordering,
+    {
+        let mut entries: Vec<_> = self.into_iter().collect();
+        entries.sort_by(|a, b| compare(a, b));
+        entries.into_iter()
+    }
+
+    /// Consumes the map into a vector of its entries, sorted by key.
+    pub fn into_sorted_vec(self) -> Vec<(K, V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = self.table.into_iter().collect();
+        // keys are unique, so a stable sort would give the same result.
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+}
+
+impl<K, V, S: Default> Default for HashMap<K, V, S> {
+    fn default() -> Self {
+        HashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Reserves room for at least `additional` more items, so that they can be inserted with at
+    /// most one resize (the one happening here).
+    pub fn reserve(&mut self, additional: usize) {
+        let hash_builder = &self.hash_builder;
+        self.table.reserve(additional, |(key, _)| make_hash(hash_builder, key));
     }
 
     /// Inserts a key-value pair into the map.
@@ -47,47 +271,184 @@ where
     /// present, the value is updated, and the old value is returned.
     ///
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        // check if resize is needed
-        if self.buckets.is_empty() || self.items > 3 * self.buckets.len() / 4 {
-            self.resize();
+        let hash = make_hash(&self.hash_builder, &key);
+        self.insert_hashed(hash, key, value)
+    }
+
+    /// Inserts every pair from `iter`, sizing the table once up front.
+    ///
+    /// The keys are hashed in a first pass, which also tells us exactly how many items are
+    /// coming, even when the iterator's length hint is useless (e.g. after a `filter`). The
+    /// table is then resized a single time and the pairs are distributed using the hashes that
+    /// were already computed. Later duplicates overwrite earlier ones, just like `insert`.
+    pub fn insert_batch<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let iter = iter.into_iter();
+        let mut hashed = Vec::with_capacity(iter.size_hint().0);
+        hashed.extend(iter.map(|(key, value)| (make_hash(&self.hash_builder, &key), key, value)));
+
+        self.reserve(hashed.len());
+        for (hash, key, value) in hashed {
+            self.insert_hashed(hash, key, value);
         }
+    }
 
-        let bucket = self.bucket(&key).expect("");
-        let bucket: &mut Vec<(K, V)> = &mut self.buckets[bucket];
+    /// Moves every entry of `other` into `self`.
+    ///
+    /// When a key is in both maps, `resolve` is called with the key, the value from `self` and
+    /// the value from `other`, and what it returns is kept.
+    pub fn merge<F>(&mut self, other: HashMap<K, V, S>, mut resolve: F)
+    where
+        F: FnMut(&K, V, V) -> V,
+    {
+        // if both maps have items, part of them probably are the same keys: don't reserve for
+        // all of them (the same guess std's `extend` makes).
+        let additional = if self.is_empty() {
+            other.len()
+        } else {
+            other.len().div_ceil(2)
+        };
+        self.reserve(additional);
 
-        // `&mut` in pattern matching dereferences the tuple it gets from the iterator
-        // with `ref`, ekey is borrowed instead of moved in the pattern.
-        // with `ref mut`, evalue is borrowed mutably instead of moved in the pattern.
-        for &mut (ref ekey, ref mut evalue) in bucket.iter_mut() {
-            if ekey == &key {
-                return Some(mem::replace(evalue, value));
+        for (key, value) in other.table {
+            match self.entry(key) {
+                Entry::Occupied(entry) => {
+                    entry.replace_entry_with(|key, old| Some(resolve(key, old, value)));
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
             }
         }
+    }
+
+    /// Moves every entry of `other` into `self`, leaving `other` empty. Values from `other`
+    /// replace those of keys already in `self`.
+    ///
+    /// Room for all of `other` is reserved up front, so the table grows at most once.
+    pub fn append(&mut self, other: &mut HashMap<K, V, S>) {
+        let other = mem::take(&mut other.table);
+        self.reserve(other.len());
+        for (key, value) in other {
+            self.insert(key, value);
+        }
+    }
+
+    fn insert_hashed(&mut self, hash: u64, key: K, value: V) -> Option<V> {
+        if let Some((_, evalue)) = self.table.find_mut(hash, |(ekey, _)| ekey == &key) {
+            return Some(mem::replace(evalue, value));
+        }
 
-        self.items += 1;
-        bucket.push((key, value));
+        let hash_builder = &self.hash_builder;
+        let hasher = |(key, _): &(K, V)| make_hash(hash_builder, key);
+        self.table.insert_unique(hash, (key, value), hasher);
         None
     }
 
+    /// Gets the entry for `key`, to inspect or modify it in place with a single lookup.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        let hash_builder = &self.hash_builder;
+        let (bucket, index) = self.table.slot(
+            hash,
+            |(ekey, _)| ekey == &key,
+            |(key, _)| make_hash(hash_builder, key),
+        );
+        match index {
+            Some(index) => Entry::Occupied(OccupiedEntry::new(Some(key), &mut self.table, index)),
+            None => Entry::Vacant(VacantEntry::new(key, table::tag(hash), bucket, &mut self.table)),
+        }
+    }
+
+    /// Returns the value of `key`, inserting `default` first if the key isn't in the map.
+    pub fn get_or_insert(&mut self, key: K, default: V) -> &mut V {
+        self.entry(key).or_insert(default)
+    }
+
+    /// Returns the value of `key`, inserting the result of `default` first if the key isn't in
+    /// the map.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V {
+        self.entry(key).or_insert_with(default)
+    }
+
+    /// Lets `f` decide the new state of `key` from its current value, with a single lookup.
+    ///
+    /// `f` gets the current value (`None` if the key isn't in the map). Returning `Some` sets the
+    /// key to the returned value, returning `None` removes it. Returns a reference to the new
+    /// value, if there is one.
+    pub fn update<F>(&mut self, key: K, f: F) -> Option<&mut V>
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        match self.entry(key) {
+            Entry::Occupied(entry) => match entry.replace_entry_with(|_, value| f(Some(value))) {
+                Entry::Occupied(entry) => Some(entry.into_mut()),
+                Entry::Vacant(_) => None,
+            },
+            Entry::Vacant(entry) => f(None).map(|value| entry.insert(value)),
+        }
+    }
+
+    /// Looks up every key of `keys`, returning their values in the same order.
+    ///
+    /// All the keys are hashed first, and the memory their lookups need is prefetched in two
+    /// rounds (their buckets, then the entries chained in them) before any of it is read. With
+    /// a map much bigger than the CPU caches the loads overlap instead of stalling one after the
+    /// other, which makes looking up thousands of keys at once noticeably faster.
+    pub fn get_batch<Q>(&self, keys: &[Q]) -> Vec<Option<&V>>
+    where
+        Q: Hash + Equivalent<K>,
+    {
+        let hashes: Vec<u64> = keys
+            .iter()
+            .map(|key| make_hash(&self.hash_builder, key))
+            .collect();
+        for &hash in &hashes {
+            self.table.prefetch_bucket(hash);
+        }
+        for &hash in &hashes {
+            self.table.prefetch_entries(hash);
+        }
+        keys.iter()
+            .zip(hashes)
+            .map(|(key, hash)| {
+                self.table
+                    .find(hash, |(ekey, _)| key.equivalent(ekey))
+                    .map(|(_, v)| v)
+            })
+            .collect()
+    }
+
     /// Returns a reference to the value corresponding to the key.
-    /// K can be borrowed as Q, so that you don't always have to provide a reference to a K
+    /// Q only has to be `Equivalent` to K, so that you don't always have to provide a reference
+    /// to a K
     pub fn get<Q>(&self, key: &Q) -> Option<&V> 
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized,
+        Q: Hash + Equivalent<K> + ?Sized,
     {
-        let bucket = self.bucket(key)?;
-        self.buckets[bucket]
-            .iter()
-            .find(|&(ref ekey, _)| ekey.borrow() == key)
-            .map(|&(_, ref v)| v)
+        let hash = make_hash(&self.hash_builder, key);
+        self.table
+            .find(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        self.table
+            .find_mut(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
     }
 
     /// Returns true if the key is in the map, false otherwise.
     pub fn contains_key<Q>(&self, key: &Q) -> bool
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized,
+        Q: Hash + Equivalent<K> + ?Sized,
     {
         self.get(key).is_some()
     }
@@ -96,90 +457,160 @@ where
     /// map.
     pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized,
+        Q: Hash + Equivalent<K> + ?Sized,
     {
-        let bucket = self.bucket(key)?;
-        let bucket = &mut self.buckets[bucket];
-        let i = bucket.iter().position(|&(ref ekey, _)| ekey.borrow() == key)?;
-        self.items -= 1;
-        Some(bucket.swap_remove(i).1)
+        let hash = make_hash(&self.hash_builder, key);
+        self.table
+            .remove(hash, |(ekey, _)| key.equivalent(ekey))
+            .map(|(_, v)| v)
     }
 
-    /// Returns the number of items that are currently in the map.
-    pub fn len(&self) -> usize {
-        self.items
+    /// Retains only the entries for which `f` returns true, removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.table.retain(|(key, value)| f(key, value));
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.items == 0
+    /// Moves the entries for which `f` returns true out to a new map, in a single pass.
+    ///
+    /// The hashes of the moved entries are kept, so none of their keys is hashed again (unless
+    /// the `compact-hashes` feature drops part of them). The new map uses a clone of the hasher
+    /// builder.
+    pub fn split_off<F>(&mut self, mut f: F) -> HashMap<K, V, S>
+    where
+        F: FnMut(&K, &V) -> bool,
+        S: Clone,
+    {
+        let hash_builder = &self.hash_builder;
+        let table = self.table.split_off(
+            |(key, value)| f(key, value),
+            |(key, _)| make_hash(hash_builder, key),
+        );
+        HashMap {
+            table,
+            hash_builder: self.hash_builder.clone(),
+        }
     }
 
-    fn resize(&mut self) {
-        let target_size = match self.buckets.len() {
-            0 => INITIAL_BUCKETS,
-            n => 2 * n,
-        };
+    /// Rebuilds the table under a freshly seeded hasher.
+    ///
+    /// Useful when [`stats`](HashMap::stats) shows keys clustering into a few buckets: the new
+    /// seed sends them to different buckets. The number of buckets stays the same.
+    ///
+    /// The new hasher builder is `S::default()`, and only [`RandomState`] draws a fresh seed
+    /// there. [`SeedableState::default`] is seed 0 every time, and hashers without a seed simply
+    /// send every key back to the bucket it was in: pick the new seed with
+    /// [`rehash_with`](HashMap::rehash_with) instead.
+    pub fn rehash(&mut self)
+    where
+        S: Default,
+    {
+        self.rehash_with(S::default());
+    }
 
-        let mut new_buckets = Vec::with_capacity(target_size);
-        new_buckets.extend((0..target_size).map(|_| Vec::new()));
+    /// Rebuilds the table under `hash_builder`, e.g. a [`SeedableState`] with another seed.
+    ///
+    /// The number of buckets stays the same.
+    pub fn rehash_with(&mut self, hash_builder: S) {
+        self.hash_builder = hash_builder;
+        let hash_builder = &self.hash_builder;
+        self.table.rehash(|(key, _)| make_hash(hash_builder, key));
+    }
+}
 
-        // so expensive!!
-        for (key, value) in self
-            .buckets
-            .iter_mut()
-            .flat_map(|bucket| bucket.drain(..))
-        {
-            let mut hasher = DefaultHasher::new();
-            key.hash(&mut hasher);
-            let bucket = (hasher.finish() % new_buckets.len() as u64) as usize;
-            new_buckets[bucket].push((key, value));
+impl<K, V, S> Clone for HashMap<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        HashMap {
+            table: self.table.clone(),
+            hash_builder: self.hash_builder.clone(),
         }
+    }
 
-        mem::replace(&mut self.buckets, new_buckets);
+    /// Overwrites `self` with a copy of `source`, reusing the allocations `self` already has.
+    ///
+    /// The table and every bucket keep their capacity, so cloning into the same map over and
+    /// over (e.g. a double-buffered snapshot) doesn't allocate once the buckets are big enough.
+    fn clone_from(&mut self, source: &Self) {
+        self.table.clone_from(&source.table);
+        // the entries were placed with `source`'s seed, so we need it too.
+        self.hash_builder = source.hash_builder.clone();
     }
 }
 
+impl<K, V, S> PartialEq for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    /// Two maps are equal if they hold the same keys with equal values, whatever their order.
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .into_iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Hash + Eq, V: Eq, S: BuildHasher> Eq for HashMap<K, V, S> {}
+
+impl<K: Hash, V: Hash, S> Hash for HashMap<K, V, S> {
+    /// Hashes every entry on its own and feeds the (wrapping) sum of their hashes to `state`,
+    /// so that equal maps hash the same whatever the order of their entries.
+    ///
+    /// The entries are hashed with a fixed-key [`DefaultHasher`], not the map's own hasher
+    /// builder: two equal maps may well have different seeds.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let sum = self.into_iter().fold(0u64, |sum, entry| {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            sum.wrapping_add(hasher.finish())
+        });
+        state.write_usize(self.len());
+        state.write_u64(sum);
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.insert_batch(iter);
+    }
+}
+
+fn make_hash<S: BuildHasher, Q: Hash + ?Sized>(hash_builder: &S, key: &Q) -> u64 {
+    // `hash_one` builds a fresh hasher everytime, so we get a fresh hash value.
+    hash_builder.hash_one(key)
+}
+
 pub struct Iter<'a, K, V> {
-    map: &'a HashMap<K, V>,
-    bucket: usize,
-    at: usize,
+    entries: table::Iter<'a, (K, V)>,
 }
 
 impl<'a, K, V> Iterator for Iter<'a, K, V> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.map.buckets.get(self.bucket) {
-                Some(bucket) => {
-                    match bucket.get(self.at) {
-                        Some(&(ref k, ref v)) => {
-                            self.at += 1;
-                            break Some((k, v));
-                        },
-                        // no more items in the bucket, move to next bucket
-                        None => {
-                            self.bucket += 1;
-                            self.at = 0;
-                            continue;
-                        },
-                    }
-                },
-                None => break None,
-            }
-        }
+        self.entries.next().map(|(k, v)| (k, v))
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a HashMap<K, V> {
+impl<'a, K, V, S> IntoIterator for &'a HashMap<K, V, S> {
     type Item = (&'a K, &'a V);
     type IntoIter = Iter<'a, K, V>;
     fn into_iter(self) -> Self::IntoIter {
         Iter {
-            map: self,
-            bucket: 0,
-            at: 0,
+            entries: self.table.iter(),
         }
     }
 }
@@ -227,11 +658,350 @@ mod tests {
         assert_eq!((&map).into_iter().count(), 4);
     }
 
+    #[test]
+    fn get_mut() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        *map.get_mut("foo").unwrap() += 1;
+        assert_eq!(map.get("foo"), Some(&43));
+        assert_eq!(map.get_mut("bar"), None);
+    }
+
     #[test]
     fn empty_hashmap() {
         let mut map = HashMap::<String, String>::new();
         assert_eq!(map.get("key"), None);
-        assert_eq!(map.contains_key("key"), false);
+        assert!(!map.contains_key("key"));
         assert_eq!(map.remove("key"), None);
     }
+
+    #[test]
+    fn equivalent_lookup() {
+        struct Pair<'a>(&'a str, &'a str);
+
+        // hashes exactly like the `(String, String)` it stands in for.
+        impl Hash for Pair<'_> {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state);
+                self.1.hash(state);
+            }
+        }
+
+        impl Equivalent<(String, String)> for Pair<'_> {
+            fn equivalent(&self, key: &(String, String)) -> bool {
+                self.0 == key.0 && self.1 == key.1
+            }
+        }
+
+        let mut map = HashMap::new();
+        map.insert(("foo".to_string(), "bar".to_string()), 42);
+        assert_eq!(map.get(&Pair("foo", "bar")), Some(&42));
+        assert!(!map.contains_key(&Pair("bar", "foo")));
+        assert_eq!(map.remove(&Pair("foo", "bar")), Some(42));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn get_or_insert() {
+        let mut map = HashMap::new();
+        *map.get_or_insert("foo", 0) += 1;
+        *map.get_or_insert("foo", 0) += 1;
+        assert_eq!(map.get_or_insert_with("bar", || 10), &mut 10);
+        assert_eq!(map.get_or_insert_with("bar", || unreachable!()), &mut 10);
+        assert_eq!(map.get("foo"), Some(&2));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn update() {
+        let mut map = HashMap::new();
+        let bump = |count: Option<u32>| Some(count.unwrap_or(0) + 1);
+        assert_eq!(map.update("foo", bump), Some(&mut 1));
+        assert_eq!(map.update("foo", bump), Some(&mut 2));
+        assert_eq!(map.update("bar", |_| None), None);
+        assert_eq!(map.len(), 1);
+
+        assert_eq!(map.update("foo", |count| count.filter(|&c| c > 2)), None);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn merge() {
+        let mut map = HashMap::new();
+        map.insert("foo", 1);
+        map.insert("bar", 2);
+        let mut other = HashMap::new();
+        other.insert("bar", 20);
+        other.insert("baz", 30);
+
+        map.merge(other, |_, a, b| a + b);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get("foo"), Some(&1));
+        assert_eq!(map.get("bar"), Some(&22));
+        assert_eq!(map.get("baz"), Some(&30));
+    }
+
+    #[test]
+    fn map_values_and_keys() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let buckets = map.table.buckets().to_vec();
+
+        let map = map.map_values(|v| v.to_string());
+        assert!(map.table.buckets() == &buckets[..]);
+        assert_eq!(map.get(&42).map(String::as_str), Some("42"));
+
+        let map = map.map_keys(|k| k % 10);
+        assert_eq!(map.len(), 10);
+        assert!(map.get(&7).unwrap().ends_with('7'));
+    }
+
+    #[test]
+    fn from_iter_grouped() {
+        let words = ["apple", "bean", "avocado", "beet", "cherry", "banana"];
+        let map = HashMap::from_iter_grouped(words.iter().map(|w| (w.as_bytes()[0], *w)));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&b'a').unwrap(), &["apple", "avocado"]);
+        assert_eq!(map.get(&b'b').unwrap(), &["bean", "beet", "banana"]);
+        assert_eq!(map.get(&b'c').unwrap(), &["cherry"]);
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut map = HashMap::with_capacity(100);
+        let buckets = map.bucket_count();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.bucket_count(), buckets);
+    }
+
+    #[test]
+    fn insert_batch() {
+        let mut map = HashMap::new();
+        map.insert(0, "zero");
+        // `filter` throws away the length hint, the batch should still size the table once.
+        map.insert_batch((1..1000).filter(|i| i % 2 == 0).map(|i| (i, "even")));
+        assert_eq!(map.len(), 500);
+        assert_eq!(map.bucket_count(), table::buckets_for(500));
+        assert_eq!(map.get(&0), Some(&"zero"));
+        assert_eq!(map.get(&998), Some(&"even"));
+        assert_eq!(map.get(&999), None);
+
+        map.extend(vec![(0, "nil"), (1, "odd")]);
+        assert_eq!(map.len(), 501);
+        assert_eq!(map.get(&0), Some(&"nil"));
+    }
+
+    #[test]
+    fn append() {
+        let mut map = HashMap::new();
+        map.extend((0..10).map(|i| (i, "self")));
+        let mut other = HashMap::new();
+        other.extend((5..1000).map(|i| (i, "other")));
+
+        map.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.bucket_count(), table::buckets_for(1005));
+        assert_eq!(map.get(&4), Some(&"self"));
+        assert_eq!(map.get(&5), Some(&"other"));
+    }
+
+    #[test]
+    fn allocated_bytes() {
+        let mut map = HashMap::new();
+        assert_eq!(map.bucket_count(), 0);
+        assert_eq!(map.allocated_bytes(), 0);
+
+        map.insert(1u64, 1u64);
+        assert_eq!(map.bucket_count(), 1);
+        // the index of the entry is stored inline in its bucket.
+        let bucket = mem::size_of::<table::Bucket>();
+        let entry = mem::size_of::<table::Slot<(u64, u64)>>();
+        assert!(map.allocated_bytes() >= bucket + entry);
+        let inline = map.allocated_bytes();
+
+        // a chain that outgrows its inline indices moves them all to the heap.
+        for i in 0..=chain::INLINE as u64 {
+            map.table.insert_unique(0, (i, i), |_| 0);
+        }
+        assert!(
+            map.allocated_bytes()
+                >= map.bucket_count() * bucket
+                    + (chain::INLINE + 2) * entry
+                    + (chain::INLINE + 1) * mem::size_of::<u32>()
+        );
+        assert!(map.allocated_bytes() > inline);
+    }
+
+    #[test]
+    fn retain() {
+        let mut map = HashMap::new();
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        map.retain(|&k, v| {
+            *v *= 10;
+            k % 2 == 0
+        });
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(&4), Some(&40));
+        assert_eq!(map.get(&5), None);
+    }
+
+    #[test]
+    fn rehash() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i * 2);
+        }
+        let buckets = map.bucket_count();
+        map.rehash();
+        assert_eq!(map.bucket_count(), buckets);
+        assert_eq!(map.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&(i * 2)));
+        }
+
+        let mut map = HashMap::with_hasher(SeedableState::with_seed(42));
+        map.extend((0..100).map(|i| (i, i * 2)));
+        map.rehash_with(SeedableState::with_seed(43));
+        assert_eq!(map.hasher().seed(), 43);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&(i * 2)));
+        }
+    }
+
+    #[test]
+    fn clone() {
+        let mut map = HashMap::new();
+        map.insert("foo".to_string(), 42);
+        map.insert("bar".to_string(), 43);
+
+        let copy = map.clone();
+        map.remove("foo");
+        assert_eq!(copy.len(), 2);
+        assert_eq!(copy.get("foo"), Some(&42));
+        assert_eq!(copy.get("bar"), Some(&43));
+    }
+
+    #[test]
+    fn clone_from_reuses_buckets() {
+        let mut source = HashMap::new();
+        for i in 0..100 {
+            source.insert(i, i);
+        }
+        let mut target = source.clone();
+        let table = target.table.buckets().as_ptr();
+
+        source.insert(0, 1000);
+        source.remove(&1);
+        target.clone_from(&source);
+        assert_eq!(target.table.buckets().as_ptr(), table);
+        assert_eq!(target.len(), 99);
+        assert_eq!(target.get(&0), Some(&1000));
+        assert_eq!(target.get(&1), None);
+        assert_eq!(target.get(&99), Some(&99));
+    }
+
+    #[test]
+    fn tags_are_compared_first() {
+        // only ever compared to itself, the tags keep any two different keys apart.
+        #[derive(Eq)]
+        struct Strict(u64);
+        impl Hash for Strict {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state);
+            }
+        }
+        impl PartialEq for Strict {
+            fn eq(&self, other: &Self) -> bool {
+                assert_eq!(self.0, other.0, "compared keys with different hashes");
+                true
+            }
+        }
+
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(Strict(i), i);
+        }
+        map.reserve(1000);
+        assert_eq!(map.get(&Strict(42)), Some(&42));
+        assert_eq!(map.get(&Strict(1000)), None);
+        assert_eq!(map.remove(&Strict(7)), Some(7));
+    }
+
+    #[test]
+    fn split_off() {
+        let mut map = HashMap::new();
+        map.extend((0..100).map(|i| (i, i)));
+        let even = map.split_off(|key, _| key % 2 == 0);
+        assert_eq!(map.len(), 50);
+        assert_eq!(even.len(), 50);
+        for i in 0..100 {
+            let (from, other) = if i % 2 == 0 { (&even, &map) } else { (&map, &even) };
+            assert_eq!(from.get(&i), Some(&i));
+            assert!(!other.contains_key(&i));
+        }
+        assert!(map.split_off(|_, _| false).is_empty());
+    }
+
+    #[test]
+    fn get_batch() {
+        let mut map = HashMap::new();
+        map.extend((0..1000).map(|i| (i, i * 2)));
+        let keys: Vec<_> = (990..1010).collect();
+        let values = map.get_batch(&keys);
+        assert_eq!(values.len(), 20);
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(value, map.get(key));
+        }
+        assert!(HashMap::<u32, u32>::new().get_batch(&[1, 2]).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn sorted() {
+        let mut map = HashMap::new();
+        map.extend((0..100).map(|i| (i, 99 - i)));
+        let by_value: Vec<_> = map.iter_sorted_by(|a, b| a.1.cmp(b.1)).collect();
+        assert_eq!(by_value.len(), 100);
+        assert_eq!(by_value[0], (&99, &0));
+        assert_eq!(by_value[99], (&0, &99));
+
+        let entries = map.into_sorted_vec();
+        assert_eq!(entries, (0..100).map(|i| (i, 99 - i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn zero_sized_values() {
+        let mut map = HashMap::new();
+        map.insert(42u64, ());
+        assert!(map.contains_key(&42));
+        // the entry is exactly as big as one holding just the key, `()` takes no space.
+        let entry = &map.table.entries()[0];
+        assert_eq!(
+            mem::size_of_val(entry),
+            mem::size_of::<table::Slot<u64>>()
+        );
+    }
+
+    #[test]
+    fn maps_as_keys() {
+        let mut a = HashMap::with_hasher(SeedableState::with_seed(1));
+        a.extend((0..100).map(|i| (i, i * 2)));
+        let mut b = HashMap::with_hasher(SeedableState::with_seed(2));
+        b.extend((0..100).rev().map(|i| (i, i * 2)));
+        assert!(a == b);
+        let state = RandomState::new();
+        assert_eq!(make_hash(&state, &a), make_hash(&state, &b));
+
+        let mut memo = HashMap::new();
+        memo.insert(a, "a");
+        assert_eq!(memo.get(&b), Some(&"a"));
+        b.insert(0, 1);
+        assert_eq!(memo.get(&b), None);
+    }
 }
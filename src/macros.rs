@@ -0,0 +1,71 @@
+//! Macros building maps and sets from their entries.
+
+/// Creates a [`HashMap`](crate::HashMap) from `key => value` pairs, with room for all of them
+/// from the start.
+///
+/// ```
+/// let map = rehash::hashmap! {
+///     "a" => 1,
+///     "b" => 2,
+/// };
+/// assert_eq!(map.len(), 2);
+/// assert_eq!(map.get("b"), Some(&2));
+/// ```
+///
+/// A key given more than once keeps its last value.
+#[macro_export]
+macro_rules! hashmap {
+    (@unit $_:expr) => {
+        ()
+    };
+    ($($key:expr => $value:expr),* $(,)?) => {{
+        let capacity = <[()]>::len(&[$($crate::hashmap!(@unit $key)),*]);
+        #[allow(unused_mut)]
+        let mut map = $crate::HashMap::with_capacity(capacity);
+        $(
+            map.insert($key, $value);
+        )*
+        map
+    }};
+}
+
+/// Creates a [`HashSet`](crate::HashSet) from its values, with room for all of them from the
+/// start.
+///
+/// ```
+/// let set = rehash::hashset! { "a", "b", "a" };
+/// assert_eq!(set.len(), 2);
+/// assert!(set.contains("a"));
+/// ```
+#[macro_export]
+macro_rules! hashset {
+    ($($value:expr),* $(,)?) => {{
+        let capacity = <[()]>::len(&[$($crate::hashmap!(@unit $value)),*]);
+        #[allow(unused_mut)]
+        let mut set = $crate::HashSet::with_capacity(capacity);
+        $(
+            set.insert($value);
+        )*
+        set
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{table, HashMap, HashSet};
+
+    #[test]
+    fn presized() {
+        let map = hashmap! { 1 => "one", 2 => "two", 3 => "three", 1 => "uno" };
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&1), Some(&"uno"));
+        assert_eq!(map.bucket_count(), table::buckets_for(4));
+
+        let empty: HashMap<u32, u32> = hashmap! {};
+        assert!(empty.is_empty());
+
+        let set: HashSet<_> = hashset! { 1, 2, 3, };
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&2));
+    }
+}
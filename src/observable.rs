@@ -0,0 +1,265 @@
+//! A map telling subscribers about every change made to it.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{self, Receiver};
+
+use crate::{make_hash, Entry, Equivalent, HashMap};
+
+/// A change made to an [`ObservableHashMap`].
+///
+/// Listeners get an `Event<&K, &V>` borrowing from the map; [`cloned`](Event::cloned) turns it
+/// into an `Event<K, V>` that can be kept around or sent elsewhere.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event<K, V> {
+    /// `key` wasn't in the map, and has been inserted with `value`.
+    Insert { key: K, value: V },
+    /// the value of `key` has been replaced: it was `old` and is `new`.
+    Update { key: K, old: V, new: V },
+    /// `key` has been removed, it had `value`.
+    Remove { key: K, value: V },
+}
+
+impl<K: Clone, V: Clone> Event<&K, &V> {
+    /// Clones the key and values the event borrows.
+    pub fn cloned(&self) -> Event<K, V> {
+        match *self {
+            Event::Insert { key, value } => Event::Insert {
+                key: key.clone(),
+                value: value.clone(),
+            },
+            Event::Update { key, old, new } => Event::Update {
+                key: key.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            },
+            Event::Remove { key, value } => Event::Remove {
+                key: key.clone(),
+                value: value.clone(),
+            },
+        }
+    }
+}
+
+/// Identifies a listener, to [`unsubscribe`](ObservableHashMap::unsubscribe) it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Subscription(u64);
+
+/// A listener, which returns false once it wants no more events.
+type Listener<K, V> = Box<dyn FnMut(&Event<&K, &V>) -> bool>;
+
+/// A map calling its listeners with an [`Event`] after every change, so that e.g. a UI or a
+/// replica can follow along without every call site having to report what it did.
+///
+/// Listeners are called in the order they subscribed, right after the change, while the map is
+/// borrowed: they can't touch the map themselves. There is no `get_mut`, since changes made
+/// through it would go unreported; [`insert`](Self::insert) the new value instead.
+pub struct ObservableHashMap<K, V, S = RandomState> {
+    map: HashMap<K, V, S>,
+    listeners: Vec<(Subscription, Listener<K, V>)>,
+    next_subscription: u64,
+}
+
+impl<K, V> ObservableHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        ObservableHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> ObservableHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        ObservableHashMap {
+            map: HashMap::with_hasher(hash_builder),
+            listeners: Vec::new(),
+            next_subscription: 0,
+        }
+    }
+
+    /// Calls `listener` with every change made from now on.
+    pub fn subscribe<F>(&mut self, mut listener: F) -> Subscription
+    where
+        F: FnMut(&Event<&K, &V>) + 'static,
+    {
+        self.add_listener(Box::new(move |event| {
+            listener(event);
+            true
+        }))
+    }
+
+    /// Returns a channel receiving every change made from now on. The map stops sending once the
+    /// receiver is dropped.
+    pub fn subscribe_channel(&mut self) -> Receiver<Event<K, V>>
+    where
+        K: Clone + 'static,
+        V: Clone + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        self.add_listener(Box::new(move |event| sender.send(event.cloned()).is_ok()));
+        receiver
+    }
+
+    /// Stops calling the listener of `subscription`. Returns false if it was gone already.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|(other, _)| *other != subscription);
+        self.listeners.len() != before
+    }
+
+    fn add_listener(&mut self, listener: Listener<K, V>) -> Subscription {
+        let subscription = Subscription(self.next_subscription);
+        self.next_subscription += 1;
+        self.listeners.push((subscription, listener));
+        subscription
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the map, dropping the listeners.
+    pub fn into_inner(self) -> HashMap<K, V, S> {
+        self.map
+    }
+}
+
+impl<K, V, S> ObservableHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair, reporting an [`Event::Insert`], or an [`Event::Update`] if the
+    /// key was in the map already.
+    ///
+    /// Returns the old value of the key, if there was one.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let old = entry.insert(value);
+                let event = Event::Update {
+                    key: entry.key(),
+                    old: &old,
+                    new: entry.get(),
+                };
+                notify(&mut self.listeners, &event);
+                Some(old)
+            }
+            Entry::Vacant(entry) => {
+                let entry = entry.insert_entry(value);
+                let event = Event::Insert {
+                    key: entry.key(),
+                    value: entry.get(),
+                };
+                notify(&mut self.listeners, &event);
+                None
+            }
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Removes a key from the map, reporting an [`Event::Remove`] if it was there. Returns its
+    /// value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        let (key, value) = self.map.table.remove(hash, |(k, _)| key.equivalent(k))?;
+        let event = Event::Remove {
+            key: &key,
+            value: &value,
+        };
+        notify(&mut self.listeners, &event);
+        Some(value)
+    }
+
+    /// Retains only the entries for which `f` returns true, reporting an [`Event::Remove`] for
+    /// every other one.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let listeners = &mut self.listeners;
+        self.map.retain(|key, value| {
+            let keep = f(key, value);
+            if !keep {
+                notify(listeners, &Event::Remove { key, value });
+            }
+            keep
+        });
+    }
+}
+
+impl<K, V, S: Default> Default for ObservableHashMap<K, V, S> {
+    fn default() -> Self {
+        ObservableHashMap::with_hasher(S::default())
+    }
+}
+
+/// Calls every listener with `event`, dropping those that don't want any more.
+fn notify<K, V>(listeners: &mut Vec<(Subscription, Listener<K, V>)>, event: &Event<&K, &V>) {
+    listeners.retain_mut(|(_, listener)| listener(event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn reports_changes() {
+        let mut map = ObservableHashMap::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = events.clone();
+        let subscription = map.subscribe(move |event| seen.borrow_mut().push(event.cloned()));
+        let receiver = map.subscribe_channel();
+
+        map.insert("a", 1);
+        map.insert("a", 2);
+        map.insert("b", 3);
+        assert_eq!(map.remove("c"), None);
+        assert_eq!(map.remove("a"), Some(2));
+        map.retain(|_, value| *value > 3);
+        assert!(map.is_empty());
+
+        let expected = vec![
+            Event::Insert { key: "a", value: 1 },
+            Event::Update {
+                key: "a",
+                old: 1,
+                new: 2,
+            },
+            Event::Insert { key: "b", value: 3 },
+            Event::Remove { key: "a", value: 2 },
+            Event::Remove { key: "b", value: 3 },
+        ];
+        assert_eq!(*events.borrow(), expected);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), expected);
+
+        assert!(map.unsubscribe(subscription));
+        assert!(!map.unsubscribe(subscription));
+        drop(receiver);
+        map.insert("c", 4);
+        assert_eq!(events.borrow().len(), expected.len());
+        assert!(map.listeners.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Saving a [`HashMap`] to a file (or any [`Write`]) and loading it back.
+//!
+//! The format is small and self-describing enough to be checked on load:
+//!
+//! - the magic bytes `RHSH` and the format version, a `u32`;
+//! - the number of entries and the number of buckets, both `u64`;
+//! - the maximum load factor, the bits of an `f32` as a `u32`;
+//! - every entry, key then value, each [`Encode`]d.
+//!
+//! All integers are little-endian, strings and sequences are prefixed with their length (a
+//! `u64`). Loading a snapshot written by a future version of the format fails with
+//! [`io::ErrorKind::InvalidData`] instead of misreading it.
+
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Read, Write};
+
+use crate::table::{buckets_for_load, HashTable, MIN_MAX_LOAD};
+use crate::{make_hash, HashMap};
+
+const MAGIC: [u8; 4] = *b"RHSH";
+/// Bumped on every change of the format.
+const VERSION: u32 = 2;
+/// Most entries a table is allocated for before they are read, the entry count of a snapshot
+/// can't be trusted with more memory than that.
+const PREALLOCATED: usize = 1 << 20;
+
+/// Types that can be written to a snapshot.
+pub trait Encode {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()>;
+}
+
+/// Types that can be read back from a snapshot.
+pub trait Decode: Sized {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self>;
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+macro_rules! persist_int {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+            }
+
+            impl Decode for $t {
+                fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    reader.read_exact(&mut bytes)?;
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+persist_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+// `usize` is written as a `u64`, so that snapshots work across pointer widths.
+impl Encode for usize {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u64).encode(writer)
+    }
+}
+
+impl Decode for usize {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        usize::try_from(u64::decode(reader)?).map_err(|_| invalid_data("length overflows usize"))
+    }
+}
+
+impl Encode for bool {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u8).encode(writer)
+    }
+}
+
+impl Decode for bool {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+}
+
+impl Encode for () {
+    fn encode<W: Write + ?Sized>(&self, _: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    fn decode<R: Read + ?Sized>(_: &mut R) -> io::Result<Self> {
+        Ok(())
+    }
+}
+
+impl Encode for str {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        self.len().encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+}
+
+impl Encode for String {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        self.as_str().encode(writer)
+    }
+}
+
+impl Decode for String {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        let len = u64::decode(reader)?;
+        let mut bytes = Vec::new();
+        // `take` keeps a corrupted length from allocating more than what is actually there.
+        reader.take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(|_| invalid_data("invalid utf-8"))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        self.len().encode(writer)?;
+        self.iter().try_for_each(|item| item.encode(writer))
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        let len = usize::decode(reader)?;
+        (0..len).map(|_| T::decode(reader)).collect()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            None => false.encode(writer),
+            Some(value) => {
+                true.encode(writer)?;
+                value.encode(writer)
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        match bool::decode(reader)? {
+            false => Ok(None),
+            true => T::decode(reader).map(Some),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Encode,
+    V: Encode,
+{
+    /// Writes a snapshot of the map to `writer`, in the format described in the
+    /// [module documentation](self).
+    ///
+    /// The writes are small, wrap files in a [`BufWriter`](std::io::BufWriter).
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        VERSION.encode(writer)?;
+        self.len().encode(writer)?;
+        self.bucket_count().encode(writer)?;
+        self.max_load_factor().to_bits().encode(writer)?;
+        for (key, value) in self {
+            key.encode(writer)?;
+            value.encode(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq + Decode,
+    V: Decode,
+    S: BuildHasher + Default,
+{
+    /// Loads a map written by [`write_to`](HashMap::write_to).
+    ///
+    /// The map gets the maximum load factor the saved map had, and room for all the entries
+    /// before any is read: loading doesn't resize, unless there are more than a million entries
+    /// (the table then grows as they come in). Its bucket count is the one its entries need, a
+    /// saved map that had grown bigger before losing entries comes back with fewer buckets. Keys
+    /// are hashed again: the hasher of the new map (`S::default()`) isn't the one the snapshot
+    /// was taken with.
+    ///
+    /// A corrupted header makes the load fail: whatever entry count it claims, no more than
+    /// room for a million entries is allocated before they are actually read.
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a map snapshot"));
+        }
+        let version = u32::decode(reader)?;
+        if version != VERSION {
+            return Err(invalid_data("unsupported snapshot version"));
+        }
+        let len = usize::decode(reader)?;
+        if len >= u32::MAX as usize {
+            return Err(invalid_data("too many entries"));
+        }
+        let buckets = usize::decode(reader)?;
+        let max_load = f32::from_bits(u32::decode(reader)?);
+        if !(max_load >= MIN_MAX_LOAD && max_load.is_finite()) {
+            return Err(invalid_data("invalid load factor"));
+        }
+        // tables only ever double from a single bucket, up to what their most entries need.
+        let valid = buckets == 0 || buckets.is_power_of_two();
+        let most = buckets_for_load(u32::MAX as usize, max_load);
+        if !valid || buckets > most || (len > 0 && buckets < buckets_for_load(len, max_load)) {
+            return Err(invalid_data("invalid bucket count"));
+        }
+
+        let mut map = HashMap::with_hasher(S::default());
+        map.table = HashTable::with_capacity_and_max_load(len.min(PREALLOCATED), max_load);
+        for _ in 0..len {
+            let key = K::decode(reader)?;
+            let value = V::decode(reader)?;
+            let hash = make_hash(&map.hash_builder, &key);
+            if map.insert_hashed(hash, key, value).is_some() {
+                return Err(invalid_data("duplicate key"));
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut map = HashMap::new();
+        for i in 0..1000u64 {
+            map.insert(i.to_string(), (i, Some(i % 2 == 0)));
+        }
+        map.remove("42");
+        let mut snapshot = Vec::new();
+        map.write_to(&mut snapshot).unwrap();
+
+        let loaded: HashMap<String, (u64, Option<bool>)> =
+            HashMap::read_from(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.len(), 999);
+        assert_eq!(loaded.bucket_count(), map.bucket_count());
+        assert_eq!(loaded.get("7"), Some(&(7, Some(false))));
+        assert_eq!(loaded.get("42"), None);
+        assert!((&map).into_iter().all(|(k, v)| loaded.get(k) == Some(v)));
+    }
+
+    #[test]
+    fn keeps_the_load_factor() {
+        let mut map = HashMap::builder().max_load_factor(4.0).build();
+        map.extend((0..100u32).map(|i| (i, i)));
+        let mut snapshot = Vec::new();
+        map.write_to(&mut snapshot).unwrap();
+
+        let loaded = HashMap::<u32, u32>::read_from(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.max_load_factor(), 4.0);
+        assert_eq!(loaded.bucket_count(), map.bucket_count());
+        assert_eq!(loaded.get(&99), Some(&99));
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let read = |bytes: &[u8]| {
+            HashMap::<u32, u32>::read_from(&mut &bytes[..])
+                .err()
+                .unwrap()
+        };
+        assert_eq!(read(b"JSON{}").kind(), io::ErrorKind::InvalidData);
+
+        let mut map = HashMap::new();
+        map.insert(1u32, 2u32);
+        let mut snapshot = Vec::new();
+        map.write_to(&mut snapshot).unwrap();
+        assert_eq!(
+            read(&snapshot[..snapshot.len() - 1]).kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        snapshot[4] = VERSION as u8 + 1;
+        assert_eq!(read(&snapshot).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_huge_headers() {
+        let header = |len: u64, buckets: u64| {
+            let mut bytes = MAGIC.to_vec();
+            VERSION.encode(&mut bytes).unwrap();
+            len.encode(&mut bytes).unwrap();
+            buckets.encode(&mut bytes).unwrap();
+            0.75f32.to_bits().encode(&mut bytes).unwrap();
+            bytes
+        };
+        let read = |bytes: Vec<u8>| {
+            HashMap::<u32, u32>::read_from(&mut bytes.as_slice())
+                .err()
+                .unwrap()
+                .kind()
+        };
+        assert_eq!(read(header(1, 1 << 40)), io::ErrorKind::InvalidData);
+        assert_eq!(read(header(1 << 40, 1 << 41)), io::ErrorKind::InvalidData);
+        // a plausible header still can't allocate for entries that aren't there.
+        assert_eq!(read(header(1 << 31, 1 << 32)), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,506 @@
+//! A persistent (immutable) hash map.
+//!
+//! [`HashMap`] is a hash array mapped trie (HAMT): a tree where each level consumes 5 bits of the
+//! key's hash to pick one of up to 32 children. `insert` and `remove` never touch the map they
+//! are called on. They copy the O(log n) nodes on the path to the key and share every other node
+//! with the old version, so keeping old versions around (snapshots, undo, an interpreter's
+//! environment chain) is cheap.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::slice;
+use std::sync::Arc;
+
+use crate::Equivalent;
+
+/// number of hash bits consumed per level of the trie.
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+pub struct HashMap<K, V> {
+    root: Arc<Node<K, V>>,
+    items: usize,
+    hash_builder: RandomState,
+}
+
+enum Node<K, V> {
+    /// `children` holds one child for every bit set in `bitmap`, in bit order.
+    Branch {
+        bitmap: u32,
+        children: Vec<Child<K, V>>,
+    },
+    /// keys whose full 64-bit hashes are identical.
+    Collision {
+        hash: u64,
+        entries: Vec<Arc<(K, V)>>,
+    },
+}
+
+enum Child<K, V> {
+    Leaf(u64, Arc<(K, V)>),
+    Node(Arc<Node<K, V>>),
+}
+
+// derive would require K: Clone and V: Clone, but we only ever clone `Arc`s.
+impl<K, V> Clone for Child<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Child::Leaf(hash, entry) => Child::Leaf(*hash, entry.clone()),
+            Child::Node(node) => Child::Node(node.clone()),
+        }
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn empty() -> Self {
+        Node::Branch {
+            bitmap: 0,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Which of the 32 children `hash` goes to at the level starting at `shift`.
+fn index(hash: u64, shift: u32) -> u32 {
+    debug_assert!(
+        shift < u64::BITS,
+        "two different hashes always split before this"
+    );
+    ((hash >> shift) & MASK) as u32
+}
+
+/// Position in `children` of the child for `index`, and whether it is present.
+fn position(bitmap: u32, index: u32) -> (usize, bool) {
+    let bit = 1 << index;
+    (
+        (bitmap & (bit - 1)).count_ones() as usize,
+        bitmap & bit != 0,
+    )
+}
+
+impl<K, V> HashMap<K, V> {
+    pub fn new() -> Self {
+        HashMap {
+            root: Arc::new(Node::empty()),
+            items: 0,
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+}
+
+impl<K, V> Default for HashMap<K, V> {
+    fn default() -> Self {
+        HashMap::new()
+    }
+}
+
+/// Cloning only bumps the reference count of the root.
+impl<K, V> Clone for HashMap<K, V> {
+    fn clone(&self) -> Self {
+        HashMap {
+            root: self.root.clone(),
+            items: self.items,
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+}
+
+impl<K, V> HashMap<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Returns a new map that also contains `key` (with `value`), leaving `self` as it was.
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let hash = self.hash_builder.hash_one(&key);
+        let (root, added) = insert(&self.root, 0, hash, Arc::new((key, value)));
+        HashMap {
+            root: Arc::new(root),
+            items: self.items + added as usize,
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+
+    /// Returns a new map without `key`, leaving `self` as it was.
+    pub fn remove<Q>(&self, key: &Q) -> Self
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(key);
+        let root = match remove(&self.root, 0, hash, key) {
+            None => return self.clone(),
+            Some(None) => Arc::new(Node::empty()),
+            Some(Some(Child::Node(node))) => node,
+            // the root is always a node, even when it is down to a single leaf: the one that is
+            // left, not the one removed.
+            Some(Some(Child::Leaf(lhash, entry))) => Arc::new(Node::Branch {
+                bitmap: 1 << index(lhash, 0),
+                children: vec![Child::Leaf(lhash, entry)],
+            }),
+        };
+        HashMap {
+            root,
+            items: self.items - 1,
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(key);
+        let mut node = &*self.root;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let (pos, present) = position(*bitmap, index(hash, shift));
+                    if !present {
+                        return None;
+                    }
+                    match &children[pos] {
+                        Child::Leaf(ehash, entry) => {
+                            return if *ehash == hash && key.equivalent(&entry.0) {
+                                Some(&entry.1)
+                            } else {
+                                None
+                            };
+                        }
+                        Child::Node(child) => {
+                            node = child;
+                            shift += BITS;
+                        }
+                    }
+                }
+                Node::Collision {
+                    hash: ehash,
+                    entries,
+                } => {
+                    if *ehash != hash {
+                        return None;
+                    }
+                    return entries
+                        .iter()
+                        .find(|entry| key.equivalent(&entry.0))
+                        .map(|entry| &entry.1);
+                }
+            }
+        }
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+/// Returns a copy of `node` with `entry` in it, and whether the key wasn't there before.
+fn insert<K: Eq, V>(
+    node: &Arc<Node<K, V>>,
+    shift: u32,
+    hash: u64,
+    entry: Arc<(K, V)>,
+) -> (Node<K, V>, bool) {
+    match **node {
+        Node::Branch {
+            bitmap,
+            ref children,
+        } => {
+            let i = index(hash, shift);
+            let (pos, present) = position(bitmap, i);
+            let mut children = children.clone();
+            if !present {
+                children.insert(pos, Child::Leaf(hash, entry));
+                let bitmap = bitmap | 1 << i;
+                return (Node::Branch { bitmap, children }, true);
+            }
+
+            let added = match children[pos] {
+                Child::Leaf(ehash, ref eentry) if ehash == hash && eentry.0 == entry.0 => {
+                    children[pos] = Child::Leaf(hash, entry);
+                    false
+                }
+                Child::Leaf(ehash, _) => {
+                    let existing = (ehash, children[pos].clone());
+                    let merged = merge(shift + BITS, existing, (hash, Child::Leaf(hash, entry)));
+                    children[pos] = Child::Node(Arc::new(merged));
+                    true
+                }
+                Child::Node(ref child) => {
+                    let (child, added) = insert(child, shift + BITS, hash, entry);
+                    children[pos] = Child::Node(Arc::new(child));
+                    added
+                }
+            };
+            (Node::Branch { bitmap, children }, added)
+        }
+        Node::Collision {
+            hash: ehash,
+            ref entries,
+        } => {
+            if ehash != hash {
+                let existing = (ehash, Child::Node(node.clone()));
+                return (
+                    merge(shift, existing, (hash, Child::Leaf(hash, entry))),
+                    true,
+                );
+            }
+            let mut entries = entries.clone();
+            let added = match entries.iter().position(|e| e.0 == entry.0) {
+                Some(i) => {
+                    entries[i] = entry;
+                    false
+                }
+                None => {
+                    entries.push(entry);
+                    true
+                }
+            };
+            (Node::Collision { hash, entries }, added)
+        }
+    }
+}
+
+/// Builds the node holding both `a` and `b`, placed at the level starting at `shift`.
+fn merge<K, V>(shift: u32, a: (u64, Child<K, V>), b: (u64, Child<K, V>)) -> Node<K, V> {
+    let ((ahash, a), (bhash, b)) = (a, b);
+    if ahash == bhash {
+        // only two leaves can get here, collision nodes absorb keys with their own hash.
+        let entries = match (a, b) {
+            (Child::Leaf(_, a), Child::Leaf(_, b)) => vec![a, b],
+            _ => unreachable!("collision node merged with a key of the same hash"),
+        };
+        return Node::Collision {
+            hash: ahash,
+            entries,
+        };
+    }
+
+    let (ai, bi) = (index(ahash, shift), index(bhash, shift));
+    let children = if ai == bi {
+        vec![Child::Node(Arc::new(merge(
+            shift + BITS,
+            (ahash, a),
+            (bhash, b),
+        )))]
+    } else if ai < bi {
+        vec![a, b]
+    } else {
+        vec![b, a]
+    };
+    Node::Branch {
+        bitmap: 1 << ai | 1 << bi,
+        children,
+    }
+}
+
+/// Removes `key` from `node`.
+///
+/// Returns `None` if the key isn't there. Otherwise returns what should take the place of
+/// `node` in its parent: nothing if it became empty, a lone leaf if that's all that is left
+/// (so the trie doesn't keep chains of single-child nodes around), or the new node.
+fn remove<K, V, Q>(
+    node: &Arc<Node<K, V>>,
+    shift: u32,
+    hash: u64,
+    key: &Q,
+) -> Option<Option<Child<K, V>>>
+where
+    Q: Equivalent<K> + ?Sized,
+{
+    match **node {
+        Node::Branch {
+            bitmap,
+            ref children,
+        } => {
+            let i = index(hash, shift);
+            let (pos, present) = position(bitmap, i);
+            if !present {
+                return None;
+            }
+            let replacement = match children[pos] {
+                Child::Leaf(ehash, ref entry) if ehash == hash && key.equivalent(&entry.0) => None,
+                Child::Leaf(..) => return None,
+                Child::Node(ref child) => remove(child, shift + BITS, hash, key)?,
+            };
+
+            let mut children = children.clone();
+            let bitmap = match replacement {
+                Some(child) => {
+                    children[pos] = child;
+                    bitmap
+                }
+                None => {
+                    children.remove(pos);
+                    bitmap & !(1 << i)
+                }
+            };
+            Some(match children.as_slice() {
+                [] => None,
+                [Child::Leaf(..)] => children.pop(),
+                _ => Some(Child::Node(Arc::new(Node::Branch { bitmap, children }))),
+            })
+        }
+        Node::Collision {
+            hash: ehash,
+            ref entries,
+        } => {
+            if ehash != hash {
+                return None;
+            }
+            let i = entries.iter().position(|entry| key.equivalent(&entry.0))?;
+            let mut entries = entries.clone();
+            entries.remove(i);
+            Some(Some(if entries.len() == 1 {
+                Child::Leaf(hash, entries.pop().unwrap())
+            } else {
+                Child::Node(Arc::new(Node::Collision { hash, entries }))
+            }))
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    /// children of the branches on the path to the current position, innermost last.
+    stack: Vec<slice::Iter<'a, Child<K, V>>>,
+    /// entries of the collision node being visited.
+    collision: slice::Iter<'a, Arc<(K, V)>>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn visit(&mut self, node: &'a Node<K, V>) {
+        match node {
+            Node::Branch { children, .. } => self.stack.push(children.iter()),
+            Node::Collision { entries, .. } => self.collision = entries.iter(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.collision.next() {
+                break Some((&entry.0, &entry.1));
+            }
+            match self.stack.last_mut()?.next() {
+                Some(Child::Leaf(_, entry)) => break Some((&entry.0, &entry.1)),
+                Some(Child::Node(node)) => self.visit(node),
+                // no more children in this branch, go back up.
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a HashMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            collision: [].iter(),
+        };
+        iter.visit(&self.root);
+        iter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_independent() {
+        let empty = HashMap::new();
+        let one = empty.insert("foo", 42);
+        let two = one.insert("bar", 43);
+        let updated = two.insert("foo", 44);
+        let removed = updated.remove("bar");
+
+        assert!(empty.is_empty());
+        assert_eq!(one.len(), 1);
+        assert_eq!(one.get("foo"), Some(&42));
+        assert_eq!(two.len(), 2);
+        assert_eq!(two.get("foo"), Some(&42));
+        assert_eq!(updated.get("foo"), Some(&44));
+        assert_eq!(updated.get("bar"), Some(&43));
+        assert_eq!(removed.len(), 1);
+        assert!(!removed.contains_key("bar"));
+        assert_eq!(removed.get("foo"), Some(&44));
+        assert_eq!(removed.remove("missing").len(), 1);
+
+        // every map draws its own seed: the two keys go to different (or the same) root
+        // children depending on it.
+        for _ in 0..200 {
+            let map = HashMap::new().insert("foo", 42).insert("bar", 43);
+            assert_eq!(map.remove("bar").get("foo"), Some(&42));
+            assert_eq!(map.remove("foo").get("bar"), Some(&43));
+        }
+    }
+
+    #[test]
+    fn many_keys() {
+        let mut map = HashMap::new();
+        for i in 0..10_000 {
+            map = map.insert(i, i * 2);
+        }
+        let full = map.clone();
+        for i in (0..10_000).filter(|i| i % 3 == 0) {
+            map = map.remove(&i);
+        }
+
+        assert_eq!(full.len(), 10_000);
+        assert_eq!((&full).into_iter().count(), 10_000);
+        assert_eq!(map.len(), 6_666);
+        assert_eq!((&map).into_iter().count(), 6_666);
+        for i in 0..10_000 {
+            assert_eq!(full.get(&i), Some(&(i * 2)));
+            assert_eq!(
+                map.get(&i).copied(),
+                if i % 3 == 0 { None } else { Some(i * 2) }
+            );
+        }
+    }
+
+    #[test]
+    fn full_hash_collisions() {
+        #[derive(PartialEq, Eq, Debug)]
+        struct Colliding(u32);
+
+        impl Hash for Colliding {
+            fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+        }
+
+        let mut map = HashMap::new();
+        for i in 0..10 {
+            map = map.insert(Colliding(i), i);
+        }
+        map = map.insert(Colliding(3), 30);
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.get(&Colliding(3)), Some(&30));
+
+        for i in 0..9 {
+            map = map.remove(&Colliding(i));
+        }
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&Colliding(9)), Some(&9));
+        assert_eq!((&map).into_iter().next(), Some((&Colliding(9), &9)));
+    }
+}
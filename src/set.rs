@@ -0,0 +1,465 @@
+//! A hash set, implemented as a [`HashMap`] with `()` values.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::{Chain, FromIterator};
+use std::mem;
+use std::ops::{BitAnd, BitOr, BitXor, Sub};
+
+use crate::{make_hash, table, Entry, Equivalent, HashMap, KeysDifference, KeysIntersection};
+
+/// A hash set, implemented as a [`HashMap`] where the value is `()`.
+///
+/// `()` takes no space, so every entry holds just the value and its hash.
+pub struct HashSet<T, S = RandomState> {
+    map: HashMap<T, (), S>,
+}
+
+impl<T> HashSet<T, RandomState> {
+    pub fn new() -> Self {
+        HashSet::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty set with enough buckets to hold at least `capacity` values without
+    /// resizing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashSet::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<T, S> HashSet<T, S> {
+    /// Creates an empty set which will use `hash_builder` to hash values.
+    pub const fn with_hasher(hash_builder: S) -> Self {
+        HashSet {
+            map: HashMap::with_hasher(hash_builder),
+        }
+    }
+
+    /// Creates an empty set with enough buckets to hold at least `capacity` values without
+    /// resizing, which will use `hash_builder` to hash values.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        HashSet {
+            map: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+        }
+    }
+
+    /// Returns a reference to the set's hasher builder.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    /// Returns the number of values that are currently in the set.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns an iterator over the values, in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            iter: self.map.into_iter(),
+        }
+    }
+}
+
+impl<T, S: Default> Default for HashSet<T, S> {
+    fn default() -> Self {
+        HashSet::with_hasher(S::default())
+    }
+}
+
+impl<T, S> HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Adds a value to the set. Returns false if it was in the set already (and leaves the set
+    /// unchanged).
+    pub fn insert(&mut self, value: T) -> bool {
+        match self.map.entry(value) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(());
+                true
+            }
+        }
+    }
+
+    /// Returns true if the value is in the set, false otherwise.
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        Q: Hash + Equivalent<T> + ?Sized,
+    {
+        self.map.contains_key(value)
+    }
+
+    /// Removes a value from the set. Returns true if it was in the set.
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        Q: Hash + Equivalent<T> + ?Sized,
+    {
+        self.map.remove(value).is_some()
+    }
+
+    /// Returns a reference to the value in the set that is equal to `value`.
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        Q: Hash + Equivalent<T> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, value);
+        self.map
+            .table
+            .find(hash, |(evalue, _)| value.equivalent(evalue))
+            .map(|(evalue, _)| evalue)
+    }
+
+    /// Removes the value equal to `value` from the set, and returns it.
+    pub fn take<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        Q: Hash + Equivalent<T> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, value);
+        self.map
+            .table
+            .remove(hash, |(evalue, _)| value.equivalent(evalue))
+            .map(|(evalue, _)| evalue)
+    }
+
+    /// Adds `value` to the set, replacing the equal value that was there and returning it.
+    pub fn replace(&mut self, value: T) -> Option<T> {
+        let hash = make_hash(&self.map.hash_builder, &value);
+        if let Some((evalue, _)) = self
+            .map
+            .table
+            .find_mut(hash, |(evalue, _)| evalue == &value)
+        {
+            return Some(mem::replace(evalue, value));
+        }
+        self.map.insert_hashed(hash, value, ());
+        None
+    }
+
+    /// Returns the value in the set that is equal to `value`, adding `f(value)` first if there
+    /// is none.
+    ///
+    /// `f(value)` has to be equal to `value` (and hash the same way), like a `String` built from
+    /// a `&str`: that's how interning pools look up an owned value by a borrowed one.
+    pub fn get_or_insert_with<Q, F>(&mut self, value: &Q, f: F) -> &T
+    where
+        Q: Hash + Equivalent<T> + ?Sized,
+        F: FnOnce(&Q) -> T,
+    {
+        let hash = make_hash(&self.map.hash_builder, value);
+        let hash_builder = &self.map.hash_builder;
+        let (bucket, index) = self.map.table.slot(
+            hash,
+            |(evalue, _)| value.equivalent(evalue),
+            |(evalue, _)| make_hash(hash_builder, evalue),
+        );
+        let index = match index {
+            Some(index) => index,
+            None => self
+                .map
+                .table
+                .insert_at(table::tag(hash), bucket, (f(value), ())),
+        };
+        &self.map.table.entries()[index].value.0
+    }
+
+    /// Retains only the values for which `f` returns true, removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|value, _| f(value));
+    }
+
+    /// Iterates over the values that are in `self` or `other`, without duplicates.
+    pub fn union<'a>(&'a self, other: &'a HashSet<T, S>) -> Union<'a, T, S> {
+        Union {
+            iter: self.iter().chain(other.difference(self)),
+        }
+    }
+
+    /// Iterates over the values that are in both `self` and `other`.
+    pub fn intersection<'a>(&'a self, other: &'a HashSet<T, S>) -> Intersection<'a, T, S> {
+        // walk the smaller set, and look its values up in the bigger one.
+        let (small, big) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Intersection {
+            iter: small.map.keys_intersection(&big.map),
+        }
+    }
+
+    /// Iterates over the values that are in `self` but not in `other`.
+    pub fn difference<'a>(&'a self, other: &'a HashSet<T, S>) -> Difference<'a, T, S> {
+        Difference {
+            iter: self.map.keys_difference(&other.map),
+        }
+    }
+
+    /// Iterates over the values that are in exactly one of `self` and `other`.
+    pub fn symmetric_difference<'a>(
+        &'a self,
+        other: &'a HashSet<T, S>,
+    ) -> SymmetricDifference<'a, T, S> {
+        SymmetricDifference {
+            iter: self.difference(other).chain(other.difference(self)),
+        }
+    }
+
+    /// Returns true if every value of `self` is in `other`.
+    pub fn is_subset(&self, other: &HashSet<T, S>) -> bool {
+        self.len() <= other.len() && self.iter().all(|value| other.contains(value))
+    }
+
+    /// Returns true if every value of `other` is in `self`.
+    pub fn is_superset(&self, other: &HashSet<T, S>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Returns true if `self` and `other` have no value in common.
+    pub fn is_disjoint(&self, other: &HashSet<T, S>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+impl<T, S> Clone for HashSet<T, S>
+where
+    T: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        HashSet {
+            map: self.map.clone(),
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.map.clone_from(&source.map);
+    }
+}
+
+impl<T, S> PartialEq for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+impl<T: Hash + Eq, S: BuildHasher> Eq for HashSet<T, S> {}
+
+impl<T: fmt::Debug, S> fmt::Debug for HashSet<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T, S> Extend<T> for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|value| (value, ())));
+    }
+}
+
+impl<T, S> FromIterator<T> for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = HashSet::default();
+        set.extend(iter);
+        set
+    }
+}
+
+macro_rules! set_operator {
+    ($trait:ident, $method:ident, $op:ident, $doc:literal) => {
+        impl<T, S> $trait<&HashSet<T, S>> for &HashSet<T, S>
+        where
+            T: Hash + Eq + Clone,
+            S: BuildHasher + Default,
+        {
+            type Output = HashSet<T, S>;
+
+            #[doc = $doc]
+            fn $method(self, other: &HashSet<T, S>) -> HashSet<T, S> {
+                self.$op(other).cloned().collect()
+            }
+        }
+    };
+}
+
+set_operator!(
+    BitOr,
+    bitor,
+    union,
+    "Returns the union of `self` and `other` as a new set."
+);
+set_operator!(
+    BitAnd,
+    bitand,
+    intersection,
+    "Returns the intersection of `self` and `other` as a new set."
+);
+set_operator!(
+    Sub,
+    sub,
+    difference,
+    "Returns the values of `self` that aren't in `other` as a new set."
+);
+set_operator!(
+    BitXor,
+    bitxor,
+    symmetric_difference,
+    "Returns the values that are in exactly one of `self` and `other` as a new set."
+);
+
+pub struct Iter<'a, T> {
+    iter: crate::Iter<'a, T, ()>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(value, _)| value)
+    }
+}
+
+impl<'a, T, S> IntoIterator for &'a HashSet<T, S> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Union<'a, T, S = RandomState> {
+    iter: Chain<Iter<'a, T>, Difference<'a, T, S>>,
+}
+
+impl<'a, T: Hash + Eq, S: BuildHasher> Iterator for Union<'a, T, S> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+pub struct Intersection<'a, T, S = RandomState> {
+    iter: KeysIntersection<'a, T, (), (), S>,
+}
+
+impl<'a, T: Hash + Eq, S: BuildHasher> Iterator for Intersection<'a, T, S> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+pub struct Difference<'a, T, S = RandomState> {
+    iter: KeysDifference<'a, T, (), (), S>,
+}
+
+impl<'a, T: Hash + Eq, S: BuildHasher> Iterator for Difference<'a, T, S> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+pub struct SymmetricDifference<'a, T, S = RandomState> {
+    iter: Chain<Difference<'a, T, S>, Difference<'a, T, S>>,
+}
+
+impl<'a, T: Hash + Eq, S: BuildHasher> Iterator for SymmetricDifference<'a, T, S> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a>(iter: impl Iterator<Item = &'a i32>) -> Vec<i32> {
+        let mut values: Vec<_> = iter.copied().collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn insert_contains_remove() {
+        let mut set = HashSet::new();
+        assert!(set.insert("foo"));
+        assert!(!set.insert("foo"));
+        assert!(set.insert("bar"));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains("foo"));
+        assert!(set.remove("foo"));
+        assert!(!set.remove("foo"));
+        assert_eq!(set.iter().collect::<Vec<_>>(), [&"bar"]);
+    }
+
+    #[test]
+    fn value_recovery() {
+        let mut pool: HashSet<String> = HashSet::new();
+        let foo = pool.get_or_insert_with("foo", str::to_string) as *const String;
+        assert_eq!(pool.get_or_insert_with("foo", |_| unreachable!()), "foo");
+        assert_eq!(pool.get("foo").map(|s| s as *const String), Some(foo));
+        assert_eq!(pool.len(), 1);
+
+        assert_eq!(pool.replace("bar".to_string()), None);
+        assert_eq!(pool.replace("bar".to_string()).as_deref(), Some("bar"));
+        assert_eq!(pool.take("foo").as_deref(), Some("foo"));
+        assert_eq!(pool.take("foo"), None);
+        assert_eq!(pool.get("bar").map(String::as_str), Some("bar"));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn set_operations() {
+        let a: HashSet<i32> = (1..=4).collect();
+        let b: HashSet<i32> = (3..=6).collect();
+        assert_eq!(sorted(a.union(&b)), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(sorted(a.intersection(&b)), [3, 4]);
+        assert_eq!(sorted(a.difference(&b)), [1, 2]);
+        assert_eq!(sorted(a.symmetric_difference(&b)), [1, 2, 5, 6]);
+
+        let small: HashSet<i32> = (2..=3).collect();
+        assert!(small.is_subset(&a));
+        assert!(a.is_superset(&small));
+        assert!(!small.is_subset(&b));
+        assert!(!a.is_disjoint(&b));
+        assert!(a.is_disjoint(&(7..9).collect()));
+    }
+
+    #[test]
+    fn operators() {
+        let a: HashSet<i32> = (1..=4).collect();
+        let b: HashSet<i32> = (3..=6).collect();
+        assert_eq!(&a | &b, (1..=6).collect());
+        assert_eq!(&a & &b, (3..=4).collect());
+        assert_eq!(&a - &b, (1..=2).collect());
+        assert_eq!(&a ^ &b, [1, 2, 5, 6].iter().copied().collect());
+    }
+}
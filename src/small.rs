@@ -0,0 +1,214 @@
+use std::hash::Hash;
+use std::mem;
+use std::slice;
+
+use crate::{Equivalent, HashMap};
+
+/// A map that keeps up to `N` entries inline, inside the struct itself.
+///
+/// While it is small, lookups are a linear search over the inline entries and nothing is
+/// allocated. Once an insert would need room for entry number `N + 1`, every entry is moved
+/// ("spilled") into a regular heap-allocated [`HashMap`] which is then used from there on.
+pub struct SmallHashMap<K, V, const N: usize = 8> {
+    repr: Repr<K, V, N>,
+}
+
+enum Repr<K, V, const N: usize> {
+    /// the first `len` slots are always `Some`.
+    Inline {
+        entries: [Option<(K, V)>; N],
+        len: usize,
+    },
+    Heap(HashMap<K, V>),
+}
+
+impl<K, V, const N: usize> SmallHashMap<K, V, N> {
+    pub fn new() -> Self {
+        SmallHashMap {
+            repr: Repr::Inline {
+                entries: std::array::from_fn(|_| None),
+                len: 0,
+            },
+        }
+    }
+
+    /// Returns true once the entries have moved to the heap.
+    pub fn spilled(&self) -> bool {
+        matches!(self.repr, Repr::Heap(_))
+    }
+}
+
+impl<K, V, const N: usize> Default for SmallHashMap<K, V, N> {
+    fn default() -> Self {
+        SmallHashMap::new()
+    }
+}
+
+impl<K, V, const N: usize> SmallHashMap<K, V, N>
+where
+    K: Hash + Eq,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key, `None` is returned. If the map did have this key
+    /// present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (entries, len) = match self.repr {
+            Repr::Inline {
+                ref mut entries,
+                ref mut len,
+            } => (entries, len),
+            Repr::Heap(ref mut map) => return map.insert(key, value),
+        };
+
+        for (ekey, evalue) in entries[..*len].iter_mut().flatten() {
+            if ekey == &key {
+                return Some(mem::replace(evalue, value));
+            }
+        }
+
+        if *len < N {
+            entries[*len] = Some((key, value));
+            *len += 1;
+            return None;
+        }
+
+        // out of inline room, move everything over to the heap.
+        let mut map = HashMap::with_capacity(N + 1);
+        map.extend(entries.iter_mut().filter_map(Option::take));
+        map.insert(key, value);
+        self.repr = Repr::Heap(map);
+        None
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.repr {
+            Repr::Inline { ref entries, len } => entries[..len]
+                .iter()
+                .flatten()
+                .find(|(ekey, _)| key.equivalent(ekey))
+                .map(|(_, v)| v),
+            Repr::Heap(ref map) => map.get(key),
+        }
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    ///
+    /// A map that has spilled stays on the heap, even if it shrinks back below `N` entries.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.repr {
+            Repr::Inline {
+                ref mut entries,
+                ref mut len,
+            } => {
+                let i = entries[..*len]
+                    .iter()
+                    .flatten()
+                    .position(|(ekey, _)| key.equivalent(ekey))?;
+                // keep the occupied slots contiguous by moving the last one into the hole.
+                *len -= 1;
+                entries.swap(i, *len);
+                entries[*len].take().map(|(_, v)| v)
+            }
+            Repr::Heap(ref mut map) => map.remove(key),
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        match self.repr {
+            Repr::Inline { len, .. } => len,
+            Repr::Heap(ref map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub enum Iter<'a, K, V> {
+    Inline(slice::Iter<'a, Option<(K, V)>>),
+    Heap(crate::Iter<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Inline(entries) => entries.next()?.as_ref().map(|(k, v)| (k, v)),
+            Iter::Heap(iter) => iter.next(),
+        }
+    }
+}
+
+impl<'a, K, V, const N: usize> IntoIterator for &'a SmallHashMap<K, V, N> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        match self.repr {
+            Repr::Inline { ref entries, len } => Iter::Inline(entries[..len].iter()),
+            Repr::Heap(ref map) => Iter::Heap(map.into_iter()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_inline() {
+        let mut map = SmallHashMap::<_, _, 4>::new();
+        for i in 0..4 {
+            assert_eq!(map.insert(i, i * 10), None);
+        }
+        assert!(!map.spilled());
+        assert_eq!(map.insert(2, 21), Some(20));
+        assert_eq!(map.get(&2), Some(&21));
+
+        assert_eq!(map.remove(&0), Some(0));
+        assert_eq!(map.remove(&0), None);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&3), Some(&30));
+        assert_eq!((&map).into_iter().count(), 3);
+    }
+
+    #[test]
+    fn spills_to_heap() {
+        let mut map = SmallHashMap::<_, _, 2>::new();
+        map.insert("foo", 42);
+        map.insert("bar", 43);
+        assert!(!map.spilled());
+        map.insert("baz", 44);
+        assert!(map.spilled());
+        assert_eq!(map.len(), 3);
+        for (&k, &v) in &map {
+            match k {
+                "foo" => assert_eq!(v, 42),
+                "bar" => assert_eq!(v, 43),
+                "baz" => assert_eq!(v, 44),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(map.remove("foo"), Some(42));
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,207 @@
+//! A hash map keeping its keys and its values in two separate arrays.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::slice;
+
+use crate::table::{self, HashTable, Slot};
+use crate::{make_hash, Equivalent};
+
+/// A hash map in struct-of-arrays layout: the table holds the keys (and their hashes), and the
+/// values sit in a parallel `Vec`, at the index of their key.
+///
+/// Both arrays are dense, and removing an entry moves the last key and the last value into its
+/// place, so the two stay in step. A scan over only the values (or only the keys) reads nothing
+/// else: [`values`](Self::values) and [`values_mut`](Self::values_mut) are plain slices, which
+/// the compiler can vectorize loops over. Lookups pay for it with one more cache miss, since the
+/// key and the value of an entry are no longer next to each other.
+///
+/// [`keys`](Self::keys) and [`values`](Self::values) list the entries in the same order.
+pub struct SoaHashMap<K, V, S = RandomState> {
+    keys: HashTable<K>,
+    values: Vec<V>,
+    hash_builder: S,
+}
+
+impl<K, V> SoaHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        SoaHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> SoaHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        SoaHashMap {
+            keys: HashTable::new(),
+            values: Vec::new(),
+            hash_builder,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Iterates over the keys, in the same order as [`values`](Self::values).
+    pub fn keys(&self) -> Keys<'_, K> {
+        Keys {
+            slots: self.keys.entries().iter(),
+        }
+    }
+
+    /// Returns the values, in the same order as [`keys`](Self::keys).
+    pub fn values(&self) -> &[V] {
+        &self.values
+    }
+
+    /// Returns the values, in the same order as [`keys`](Self::keys).
+    pub fn values_mut(&mut self) -> &mut [V] {
+        &mut self.values
+    }
+
+    /// Iterates over the entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.keys().zip(&self.values)
+    }
+}
+
+impl<K, V, S: Default> Default for SoaHashMap<K, V, S> {
+    fn default() -> Self {
+        SoaHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> SoaHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn index<Q>(&self, key: &Q) -> Option<usize>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        self.keys.find_index(hash, |ekey| key.equivalent(ekey))
+    }
+
+    /// Inserts a key-value pair into the map, returning the old value of the key if it was
+    /// there.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.hash_builder, &key);
+        let hash_builder = &self.hash_builder;
+        let (bucket, index) = self.keys.slot(
+            hash,
+            |ekey| ekey == &key,
+            |key| make_hash(hash_builder, key),
+        );
+        match index {
+            Some(index) => Some(mem::replace(&mut self.values[index], value)),
+            None => {
+                // the key goes at the end of the entries, which is where the value goes too.
+                self.keys.insert_at(table::tag(hash), bucket, key);
+                self.values.push(value);
+                None
+            }
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.index(key).map(|index| &self.values[index])
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let index = self.index(key)?;
+        Some(&mut self.values[index])
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.index(key).is_some()
+    }
+
+    /// Removes a key from the map, returning its value if it was there.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let index = self.index(key)?;
+        Some(self.remove_at(index))
+    }
+
+    /// Retains only the entries for which `f` returns true, removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut index = 0;
+        while index < self.values.len() {
+            if f(&self.keys.entries()[index].value, &mut self.values[index]) {
+                index += 1;
+            } else {
+                // the last entry moves to `index`, and has to be looked at next.
+                self.remove_at(index);
+            }
+        }
+    }
+
+    fn remove_at(&mut self, index: usize) -> V {
+        // both move their last item into `index`.
+        self.keys.remove_at(index);
+        self.values.swap_remove(index)
+    }
+}
+
+pub struct Keys<'a, K> {
+    slots: slice::Iter<'a, Slot<K>>,
+}
+
+impl<'a, K> Iterator for Keys<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slots.next().map(|slot| &slot.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_and_values_stay_in_step() {
+        let mut map = SoaHashMap::new();
+        for i in 0..100 {
+            assert_eq!(map.insert(i, i * 10), None);
+        }
+        assert_eq!(map.insert(5, 5), Some(50));
+        assert_eq!(map.remove(&0), Some(0));
+        map.retain(|key, _| key % 3 != 0);
+        for value in map.values_mut() {
+            *value += 1;
+        }
+
+        assert_eq!(map.len(), 66);
+        assert_eq!(map.get(&5), Some(&6));
+        assert_eq!(map.get(&3), None);
+        for (key, value) in map.iter() {
+            let expected = if *key == 5 { 6 } else { key * 10 + 1 };
+            assert_eq!(*value, expected);
+        }
+    }
+}
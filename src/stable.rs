@@ -0,0 +1,200 @@
+use std::cell::{Cell, UnsafeCell};
+use std::hash::Hash;
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+
+use crate::{Equivalent, HashMap};
+
+/// A map whose values never move, so references to them stay valid across inserts.
+///
+/// Every value lives in its own heap allocation, and inserting only needs `&self`: a `&V`
+/// handed out by [`get`] or [`insert`] keeps pointing at the same value while more entries are
+/// added (and the table resizes underneath). Values can only go away through methods taking
+/// `&mut self`, which the borrow checker won't allow while such references are alive. This is
+/// what intrusive or self-referencing structures (like graphs whose nodes point at each other)
+/// need.
+///
+/// [`get`]: StableHashMap::get
+/// [`insert`]: StableHashMap::insert
+pub struct StableHashMap<K, V> {
+    map: UnsafeCell<HashMap<K, Slot<V>>>,
+    /// set while `map` is being accessed, so that a `Hash` or `Eq` impl calling back into the
+    /// map can't get a second reference to it.
+    in_use: Cell<bool>,
+}
+
+/// Owns a heap-allocated `V`, like a `Box`.
+///
+/// A `Box` asserts unique access to its contents whenever it is moved, which would invalidate
+/// the references we hand out each time the table resizes. A raw pointer makes no such claim.
+struct Slot<V>(NonNull<V>);
+
+// SAFETY: a `Slot` owns its value just like a `Box` does.
+unsafe impl<V: Send> Send for Slot<V> {}
+
+impl<V> Slot<V> {
+    fn new(value: V) -> Self {
+        Slot(NonNull::from(Box::leak(Box::new(value))))
+    }
+
+    fn into_inner(self) -> V {
+        let slot = ManuallyDrop::new(self);
+        // SAFETY: the pointer came from `Box::leak` and `slot` won't free it again.
+        *unsafe { Box::from_raw(slot.0.as_ptr()) }
+    }
+}
+
+impl<V> Drop for Slot<V> {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `Box::leak` and is only freed here.
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+/// Clears `in_use` when dropped, even if `Hash` or `Eq` panicked.
+struct InUse<'a>(&'a Cell<bool>);
+
+impl Drop for InUse<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+impl<K, V> StableHashMap<K, V> {
+    pub fn new() -> Self {
+        StableHashMap {
+            map: UnsafeCell::new(HashMap::new()),
+            in_use: Cell::new(false),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the inner map.
+    fn with_map<R>(&self, f: impl FnOnce(&mut HashMap<K, Slot<V>>) -> R) -> R {
+        assert!(
+            !self.in_use.replace(true),
+            "StableHashMap accessed from within the hash or eq of its own key"
+        );
+        let _in_use = InUse(&self.in_use);
+        // SAFETY: `in_use` makes sure this is the only reference to the map right now, and the
+        // map isn't `Sync` (because of the cells) so no other thread can get in either.
+        f(unsafe { &mut *self.map.get() })
+    }
+}
+
+impl<K, V> Default for StableHashMap<K, V> {
+    fn default() -> Self {
+        StableHashMap::new()
+    }
+}
+
+impl<K, V> StableHashMap<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Inserts `value` under `key` if the key isn't in the map yet, and returns a reference to
+    /// the value stored under `key`.
+    ///
+    /// An existing value is never replaced (that would invalidate references to it), `value`
+    /// is dropped instead.
+    pub fn insert(&self, key: K, value: V) -> &V {
+        self.get_or_insert_with(key, || value)
+    }
+
+    /// Returns the value of `key`, inserting the result of `default` first if the key isn't in
+    /// the map.
+    ///
+    /// `default` runs while the map is not being accessed, so it may use the map itself.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&self, key: K, default: F) -> &V {
+        let slot = match self.get(&key) {
+            Some(value) => return value,
+            None => Slot::new(default()),
+        };
+        let value = self.with_map(|map| map.get_or_insert(key, slot).0);
+        // SAFETY: the value has its own allocation, so it stays where it is when the table
+        // resizes, and it can only be replaced or dropped through `&mut self`, which can't
+        // happen while the returned reference (tied to `&self`) is alive.
+        unsafe { value.as_ref() }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let value = self.with_map(|map| map.get(key).map(|slot| slot.0))?;
+        // SAFETY: see `get_or_insert_with`.
+        Some(unsafe { value.as_ref() })
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.with_map(|map| map.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        // SAFETY: `&mut self` guarantees no other reference to the value is alive.
+        let slot = self.map.get_mut().get_mut(key)?;
+        Some(unsafe { slot.0.as_mut() })
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get_mut().remove(key).map(Slot::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_survive_inserts() {
+        let map = StableHashMap::new();
+        let first = map.insert(0, "zero".to_string());
+        // plenty of resizes happen on the way.
+        for i in 1..1000 {
+            map.insert(i, i.to_string());
+        }
+        assert_eq!(first, "zero");
+        assert_eq!(map.insert(0, "nil".to_string()), "zero");
+        assert_eq!(map.get(&999).map(String::as_str), Some("999"));
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn mutation_needs_exclusive_access() {
+        let mut map = StableHashMap::new();
+        map.insert("foo", 42);
+        *map.get_mut("foo").unwrap() += 1;
+        assert_eq!(map.remove("foo"), Some(43));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn default_may_use_the_map() {
+        let map = StableHashMap::new();
+        map.insert(1, 10);
+        let two = map.get_or_insert_with(2, || map.get(&1).unwrap() * 2);
+        assert_eq!(two, &20);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+
+use crate::HashMap;
+
+/// Widest bar printed by the `Display` impl of [`Distribution`].
+const MAX_BAR: usize = 50;
+
+/// A snapshot of how well the entries of a [`HashMap`] are spread over its buckets.
+///
+/// Returned by [`HashMap::stats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// number of items divided by number of buckets.
+    pub load_factor: f64,
+    pub buckets: usize,
+    pub non_empty_buckets: usize,
+    /// average length of the non-empty chains.
+    pub average_chain: f64,
+    pub max_chain: usize,
+    /// number of entries that share their bucket with an entry that got there first.
+    pub collisions: usize,
+}
+
+/// Histogram of bucket occupancy, returned by [`HashMap::debug_distribution`].
+///
+/// `histogram[n]` is the number of buckets holding exactly `n` entries. Printing it with `{}`
+/// draws one bar per chain length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Distribution {
+    pub histogram: Vec<usize>,
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let widest = self.histogram.iter().copied().max().unwrap_or(0);
+        writeln!(f, "chain length | buckets")?;
+        for (len, &count) in self.histogram.iter().enumerate() {
+            // round up so that a non-empty row always gets at least one mark.
+            let bar = (count * MAX_BAR).div_ceil(widest.max(1));
+            writeln!(f, "{:>12} | {:<8} {}", len, count, "#".repeat(bar))?;
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    /// Walks every bucket and reports on the current layout of the table.
+    ///
+    /// With a good hash function the chains stay short (close to the load factor); a
+    /// `max_chain` that keeps growing with the map is a sign that keys are clustering.
+    pub fn stats(&self) -> Stats {
+        let table = self.table.buckets();
+        let buckets = table.len();
+        let non_empty_buckets = table.iter().filter(|b| !b.is_empty()).count();
+        let max_chain = table.iter().map(|bucket| bucket.len()).max().unwrap_or(0);
+        let items = self.len();
+
+        let ratio = |a: usize, b: usize| if b == 0 { 0.0 } else { a as f64 / b as f64 };
+        Stats {
+            load_factor: ratio(items, buckets),
+            buckets,
+            non_empty_buckets,
+            average_chain: ratio(items, non_empty_buckets),
+            max_chain,
+            collisions: items - non_empty_buckets,
+        }
+    }
+
+    /// Counts how many buckets hold 0, 1, 2, ... entries.
+    ///
+    /// When lookups get slow, `println!("{}", map.debug_distribution())` shows at a glance
+    /// whether everything is piling into a few buckets.
+    pub fn debug_distribution(&self) -> Distribution {
+        let mut histogram = Vec::new();
+        for bucket in self.table.buckets() {
+            if histogram.len() <= bucket.len() {
+                histogram.resize(bucket.len() + 1, 0);
+            }
+            histogram[bucket.len()] += 1;
+        }
+        Distribution { histogram }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let map = HashMap::<u32, u32>::new();
+        let stats = map.stats();
+        assert_eq!(stats.load_factor, 0.0);
+        assert_eq!(stats.max_chain, 0);
+        assert_eq!(stats.collisions, 0);
+    }
+
+    #[test]
+    fn single_bucket() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        let stats = map.stats();
+        assert_eq!(stats.buckets, 1);
+        assert_eq!(stats.non_empty_buckets, 1);
+        assert_eq!(stats.load_factor, 1.0);
+        assert_eq!(stats.average_chain, 1.0);
+        assert_eq!(stats.max_chain, 1);
+        assert_eq!(stats.collisions, 0);
+    }
+
+    #[test]
+    fn chains_add_up() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.non_empty_buckets + stats.collisions, 100);
+        assert!(stats.max_chain >= 1);
+        assert!(stats.average_chain >= 1.0);
+    }
+
+    #[test]
+    fn distribution() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let distribution = map.debug_distribution();
+        let buckets: usize = distribution.histogram.iter().sum();
+        let items: usize = distribution
+            .histogram
+            .iter()
+            .enumerate()
+            .map(|(len, count)| len * count)
+            .sum();
+        assert_eq!(buckets, map.bucket_count());
+        assert_eq!(items, 100);
+
+        let printed = distribution.to_string();
+        assert!(printed.starts_with("chain length | buckets\n"));
+        assert_eq!(printed.lines().count(), distribution.histogram.len() + 1);
+    }
+}
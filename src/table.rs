@@ -0,0 +1,649 @@
+//! The raw hash table [`HashMap`](crate::HashMap) is built on.
+
+use std::iter;
+use std::{mem, slice, vec};
+
+use crate::chain::Chain;
+use crate::INITIAL_BUCKETS;
+
+/// The part of its hash every entry keeps next to it.
+///
+/// By default that's the whole 64-bit hash: lookups compare it before calling `eq`, and resizing
+/// never needs to hash an entry again. The `compact-hashes` feature keeps only the upper 32 bits
+/// instead, saving 4 bytes (often 8, with padding) per entry. Lookups still skip most
+/// unequal entries, but every resize has to hash all entries again.
+#[cfg(not(feature = "compact-hashes"))]
+pub(crate) type HashTag = u64;
+#[cfg(feature = "compact-hashes")]
+pub(crate) type HashTag = u32;
+
+pub(crate) fn tag(hash: u64) -> HashTag {
+    // the upper bits, the lower ones pick the bucket and are the same for a whole chain anyway.
+    (hash >> (u64::BITS - HashTag::BITS)) as HashTag
+}
+
+/// The full hash a tag was made from, if it kept all of it.
+#[cfg(not(feature = "compact-hashes"))]
+fn untag(tag: HashTag) -> Option<u64> {
+    Some(tag)
+}
+#[cfg(feature = "compact-hashes")]
+fn untag(_: HashTag) -> Option<u64> {
+    None
+}
+
+/// An entry of the table, with the tag of its hash and the bucket it is chained in.
+#[derive(Clone)]
+pub(crate) struct Slot<T> {
+    pub(crate) tag: HashTag,
+    bucket: u32,
+    pub(crate) value: T,
+}
+
+impl<T> Slot<T> {
+    pub(crate) fn bucket(&self) -> usize {
+        self.bucket as usize
+    }
+}
+
+/// Indices (into the entries of the table) of the entries chained in a bucket.
+///
+/// The first couple of indices are stored inline, so most buckets never allocate.
+pub(crate) type Bucket = Chain<u32>;
+
+/// A hash table implemented with separate chaining, which leaves hashing and comparing its
+/// entries to the caller.
+///
+/// Every operation takes the hash of the entry it's looking for together with a closure telling
+/// whether a stored entry is the one, and the operations that may grow the table take a
+/// `hasher` closure to compute the hash of a stored entry. This is the machinery behind
+/// [`HashMap`](crate::HashMap), for structures that don't fit a key-value API: string
+/// interners storing only an index, tables that can be looked up by more than one key, ...
+///
+/// All entries live next to each other in a single `Vec`, and the buckets only chain their
+/// indices. Iterating is a linear scan, and growing the table moves small indices around instead
+/// of the entries. Removing an entry moves the last one into its place.
+///
+/// The table trusts the caller: an entry has to be looked up with the hash it was inserted with,
+/// and `hasher` has to give back that same hash. Otherwise entries go missing (but nothing
+/// unsafe happens).
+pub struct HashTable<T> {
+    entries: Vec<Slot<T>>,
+    buckets: Vec<Bucket>,
+    /// average number of entries per bucket the table grows past.
+    max_load: f32,
+}
+
+/// Default [`max_load`](HashTable::max_load) of a table.
+pub(crate) const DEFAULT_MAX_LOAD: f32 = 0.75;
+
+/// Smallest [`max_load`](HashTable::max_load) a table accepts: ten buckets per entry already
+/// waste most of the memory, below that the bucket count gets absurd (and eventually overflows).
+pub(crate) const MIN_MAX_LOAD: f32 = 0.1;
+
+impl<T> HashTable<T> {
+    pub const fn new() -> Self {
+        HashTable {
+            // allocation happens during initial insert.
+            entries: Vec::new(),
+            buckets: Vec::new(),
+            max_load: DEFAULT_MAX_LOAD,
+        }
+    }
+
+    /// Creates an empty table which grows once it has more than `max_load` entries per bucket on
+    /// average, with enough buckets to hold at least `capacity` items without resizing.
+    ///
+    /// # Panics
+    ///
+    /// If `max_load` is below 0.1, or isn't a finite number.
+    pub fn with_capacity_and_max_load(capacity: usize, max_load: f32) -> Self {
+        assert!(
+            max_load >= MIN_MAX_LOAD && max_load.is_finite(),
+            "the maximum load factor has to be at least {}, got {}",
+            MIN_MAX_LOAD,
+            max_load
+        );
+        let mut table = HashTable::new();
+        table.max_load = max_load;
+        if capacity > 0 {
+            table.entries.reserve_exact(capacity);
+            let buckets = buckets_for_load(capacity, max_load);
+            table.buckets.extend((0..buckets).map(|_| Chain::new()));
+        }
+        table
+    }
+
+    /// Creates an empty table with enough buckets to hold at least `capacity` items without
+    /// resizing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashTable::with_capacity_and_max_load(capacity, DEFAULT_MAX_LOAD)
+    }
+
+    /// Returns the number of items that are currently in the table.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the number of buckets the table currently has.
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns the average number of entries per bucket the table grows past (3/4 by default).
+    pub fn max_load(&self) -> f32 {
+        self.max_load
+    }
+
+    /// Returns the number of bytes the table has allocated on the heap: the entries, the bucket
+    /// table, and the indices of every bucket that outgrew its inline ones.
+    pub fn allocated_bytes(&self) -> usize {
+        let entries = self.entries.capacity() * mem::size_of::<Slot<T>>();
+        let table = self.buckets.capacity() * mem::size_of::<Bucket>();
+        let indices: usize = self.buckets.iter().map(Chain::heap_capacity).sum();
+        entries + table + indices * mem::size_of::<u32>()
+    }
+
+    pub(crate) fn buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
+
+    pub(crate) fn entries(&self) -> &[Slot<T>] {
+        &self.entries
+    }
+
+    pub(crate) fn entries_mut(&mut self) -> &mut [Slot<T>] {
+        &mut self.entries
+    }
+
+    fn bucket_for_hash(&self, hash: u64) -> usize {
+        // TODO: Implement something better than modulo
+        (hash % self.buckets.len() as u64) as usize
+    }
+
+    /// Returns the index of the entry with hash `hash` for which `eq` returns true.
+    pub(crate) fn find_index<F>(&self, hash: u64, mut eq: F) -> Option<usize>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let tag = tag(hash);
+        self.buckets[self.bucket_for_hash(hash)]
+            .iter()
+            .map(|&index| index as usize)
+            .find(|&index| {
+                let slot = &self.entries[index];
+                slot.tag == tag && eq(&slot.value)
+            })
+    }
+
+    /// Hints the CPU to start loading the bucket of `hash` into the cache.
+    pub(crate) fn prefetch_bucket(&self, hash: u64) {
+        if !self.buckets.is_empty() {
+            prefetch(&self.buckets[self.bucket_for_hash(hash)]);
+        }
+    }
+
+    /// Hints the CPU to start loading the entries chained in the bucket of `hash` into the
+    /// cache. Reads the bucket, so it's best done once [`prefetch_bucket`] had time to load it.
+    ///
+    /// [`prefetch_bucket`]: HashTable::prefetch_bucket
+    pub(crate) fn prefetch_entries(&self, hash: u64) {
+        if self.buckets.is_empty() {
+            return;
+        }
+        for &index in &self.buckets[self.bucket_for_hash(hash)] {
+            prefetch(&self.entries[index as usize]);
+        }
+    }
+
+    /// Returns the entry with hash `hash` for which `eq` returns true.
+    pub fn find<F>(&self, hash: u64, eq: F) -> Option<&T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let index = self.find_index(hash, eq)?;
+        Some(&self.entries[index].value)
+    }
+
+    /// Like [`find`](HashTable::find), but returns a mutable reference.
+    ///
+    /// Whatever part of the entry its hash is computed from must not be changed through it.
+    pub fn find_mut<F>(&mut self, hash: u64, eq: F) -> Option<&mut T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let index = self.find_index(hash, eq)?;
+        Some(&mut self.entries[index].value)
+    }
+
+    /// Returns the number of entries in the chain a lookup of hash `hash` searches.
+    pub(crate) fn chain_len(&self, hash: u64) -> usize {
+        if self.buckets.is_empty() {
+            return 0;
+        }
+        self.buckets[self.bucket_for_hash(hash)].len()
+    }
+
+    /// Inserts `value` with hash `hash`, without checking whether an equal entry already is in
+    /// the table. Returns a mutable reference to the inserted entry.
+    pub fn insert_unique<H>(&mut self, hash: u64, value: T, hasher: H) -> &mut T
+    where
+        H: Fn(&T) -> u64,
+    {
+        self.reserve(1, hasher);
+        let bucket = self.bucket_for_hash(hash);
+        let index = self.insert_at(tag(hash), bucket, value);
+        &mut self.entries[index].value
+    }
+
+    /// Chains `value` in `bucket` without making room for it first, and returns its index.
+    pub(crate) fn insert_at(&mut self, tag: HashTag, bucket: usize, value: T) -> usize {
+        let index = self.entries.len();
+        assert!(index < u32::MAX as usize, "a table holds fewer than 2^32 entries");
+        self.entries.push(Slot {
+            tag,
+            bucket: bucket as u32,
+            value,
+        });
+        self.buckets[bucket].push(index as u32);
+        index
+    }
+
+    /// Removes the entry with hash `hash` for which `eq` returns true, and returns it.
+    pub fn remove<F>(&mut self, hash: u64, eq: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let index = self.find_index(hash, eq)?;
+        Some(self.remove_at(index).value)
+    }
+
+    /// Removes the entry at `index`, moving the last entry into its place.
+    pub(crate) fn remove_at(&mut self, index: usize) -> Slot<T> {
+        let slot = self.entries.swap_remove(index);
+        let bucket = &mut self.buckets[slot.bucket as usize];
+        let at = bucket.iter().position(|&i| i as usize == index).unwrap();
+        bucket.swap_remove(at);
+
+        if let Some(moved) = self.entries.get(index) {
+            // the last entry took the place of the removed one, its bucket has to follow.
+            let last = self.entries.len() as u32;
+            let bucket = &mut self.buckets[moved.bucket as usize];
+            *bucket.iter_mut().find(|i| **i == last).unwrap() = index as u32;
+        }
+        slot
+    }
+
+    /// Retains only the entries for which `f` returns true, removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let mut index = 0;
+        while index < self.entries.len() {
+            if f(&mut self.entries[index].value) {
+                index += 1;
+            } else {
+                // the last entry moves to `index`, and has to be looked at next.
+                self.remove_at(index);
+            }
+        }
+    }
+
+    /// Moves the entries for which `f` returns true out to a new table, and returns it.
+    ///
+    /// The entries keep their hash tags, so `hasher` is only called for those whose tag doesn't
+    /// hold the whole hash.
+    pub fn split_off<F, H>(&mut self, mut f: F, hasher: H) -> HashTable<T>
+    where
+        F: FnMut(&T) -> bool,
+        H: Fn(&T) -> u64,
+    {
+        let mut table = HashTable::new();
+        table.max_load = self.max_load;
+        let mut index = 0;
+        while index < self.entries.len() {
+            if f(&self.entries[index].value) {
+                // the last entry moves to `index`, and has to be looked at next.
+                table.entries.push(self.remove_at(index));
+            } else {
+                index += 1;
+            }
+        }
+        if !table.entries.is_empty() {
+            let buckets = buckets_for_load(table.entries.len(), table.max_load);
+            table.rebuild(buckets, hasher, false);
+        }
+        table
+    }
+
+    /// Reserves room for at least `additional` more items, so that they can be inserted with at
+    /// most one resize (the one happening here).
+    pub fn reserve<H>(&mut self, additional: usize, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        if additional == 0 {
+            return;
+        }
+        self.entries.reserve(additional);
+        let target_size = buckets_for_load(self.entries.len() + additional, self.max_load);
+        if target_size > self.buckets.len() {
+            self.rebuild(target_size, hasher, false);
+        }
+    }
+
+    /// Moves every entry to the bucket of the hash `hasher` gives it now, e.g. after the caller
+    /// switched to a hasher with another seed. The number of buckets stays the same.
+    pub fn rehash<H>(&mut self, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        self.rebuild(self.buckets.len(), hasher, true);
+    }
+
+    /// Finds the entry with hash `hash` for which `eq` returns true, after making room for one
+    /// more entry: returns the bucket it is (or would go) in, and its index if it's there.
+    pub(crate) fn slot<F, H>(&mut self, hash: u64, eq: F, hasher: H) -> (usize, Option<usize>)
+    where
+        F: FnMut(&T) -> bool,
+        H: Fn(&T) -> u64,
+    {
+        // grow now, so that inserting into the bucket can't move the entry to another one.
+        self.reserve(1, hasher);
+        (self.bucket_for_hash(hash), self.find_index(hash, eq))
+    }
+
+    /// Chains every entry again in a new table of `target_size` buckets, hashing the entries
+    /// again if `rehash_all` is set or the stored tags don't hold the whole hash.
+    fn rebuild<H>(&mut self, target_size: usize, hasher: H, rehash_all: bool)
+    where
+        H: Fn(&T) -> u64,
+    {
+        let mut new_buckets = Vec::with_capacity(target_size);
+        new_buckets.extend((0..target_size).map(|_| Chain::new()));
+
+        for (index, slot) in self.entries.iter_mut().enumerate() {
+            // so expensive!!
+            let hash = match untag(slot.tag) {
+                Some(hash) if !rehash_all => hash,
+                _ => hasher(&slot.value),
+            };
+            let bucket = (hash % new_buckets.len() as u64) as usize;
+            slot.tag = tag(hash);
+            slot.bucket = bucket as u32;
+            new_buckets[bucket].push(index as u32);
+        }
+
+        self.buckets = new_buckets;
+    }
+
+    /// Takes every entry out of the table together with its full hash, only calling `hasher`
+    /// for the entries whose tag doesn't hold the whole hash.
+    pub(crate) fn into_hashed<H>(self, hasher: H) -> Vec<(u64, T)>
+    where
+        H: Fn(&T) -> u64,
+    {
+        self.entries
+            .into_iter()
+            .map(|slot| {
+                let hash = untag(slot.tag).unwrap_or_else(|| hasher(&slot.value));
+                (hash, slot.value)
+            })
+            .collect()
+    }
+
+    /// Turns every entry into `f(entry)`, keeping the layout of the table.
+    ///
+    /// The new entries stay where the old ones were, so `f` must not change whatever part of
+    /// the entry its hash is computed from.
+    pub(crate) fn map<U, F>(self, mut f: F) -> HashTable<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        HashTable {
+            entries: self
+                .entries
+                .into_iter()
+                .map(|slot| Slot {
+                    tag: slot.tag,
+                    bucket: slot.bucket,
+                    value: f(slot.value),
+                })
+                .collect(),
+            buckets: self.buckets,
+            max_load: self.max_load,
+        }
+    }
+
+    /// Returns an iterator over the entries, in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let (tail, head) = self.entries.split_at(iteration_start(self.entries.len()));
+        Iter {
+            entries: head.iter().chain(tail),
+        }
+    }
+}
+
+impl<T> Default for HashTable<T> {
+    fn default() -> Self {
+        HashTable::new()
+    }
+}
+
+impl<T: Clone> Clone for HashTable<T> {
+    fn clone(&self) -> Self {
+        HashTable {
+            entries: self.entries.clone(),
+            buckets: self.buckets.clone(),
+            max_load: self.max_load,
+        }
+    }
+
+    /// Overwrites `self` with a copy of `source`, reusing the allocations `self` already has.
+    ///
+    /// The entries, the table and every bucket keep their capacity, so cloning into the same
+    /// table over and over (e.g. a double-buffered snapshot) doesn't allocate once they are big
+    /// enough.
+    fn clone_from(&mut self, source: &Self) {
+        self.entries.clone_from(&source.entries);
+        self.buckets.truncate(source.buckets.len());
+        for (bucket, source_bucket) in self.buckets.iter_mut().zip(&source.buckets) {
+            bucket.clone_from(source_bucket);
+        }
+        let cloned = self.buckets.len();
+        self.buckets.extend_from_slice(&source.buckets[cloned..]);
+        self.max_load = source.max_load;
+    }
+}
+
+/// Smallest bucket count (following the doubling sequence used when growing) that can hold
+/// `items` items without triggering another resize.
+pub(crate) fn buckets_for(items: usize) -> usize {
+    buckets_for_load(items, DEFAULT_MAX_LOAD)
+}
+
+/// [`buckets_for`] a table with a maximum load factor of `max_load`.
+pub(crate) fn buckets_for_load(items: usize, max_load: f32) -> usize {
+    let mut buckets = INITIAL_BUCKETS;
+    // inserting resizes once `items > max_load * buckets` *before* adding the new item.
+    while items.saturating_sub(1) as f64 > f64::from(max_load) * buckets as f64 {
+        buckets *= 2;
+    }
+    buckets
+}
+
+/// Hints the CPU to start loading the cache line `value` is in, without waiting for it.
+#[cfg(target_arch = "x86_64")]
+fn prefetch<T>(value: &T) {
+    use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+    // SAFETY: prefetching never faults, whatever the address, and SSE is always there on x86_64.
+    unsafe { _mm_prefetch::<_MM_HINT_T0>((value as *const T).cast()) }
+}
+#[cfg(not(target_arch = "x86_64"))]
+fn prefetch<T>(_: &T) {}
+
+/// The entry iteration starts from.
+///
+/// With the `randomize-iteration` feature that's a different entry every time, so that code
+/// depending on the order of a map (which changes with the hasher or the order of removals)
+/// breaks right away instead of after an upgrade.
+#[cfg(not(feature = "randomize-iteration"))]
+fn iteration_start(_: usize) -> usize {
+    0
+}
+#[cfg(feature = "randomize-iteration")]
+fn iteration_start(entries: usize) -> usize {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    if entries == 0 {
+        return 0;
+    }
+    // every `RandomState` has new keys, so even hashing nothing gives a fresh value.
+    (RandomState::new().build_hasher().finish() % entries as u64) as usize
+}
+
+pub struct Iter<'a, T> {
+    entries: iter::Chain<slice::Iter<'a, Slot<T>>, slice::Iter<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|slot| &slot.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a HashTable<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct IntoIter<T> {
+    entries: vec::IntoIter<Slot<T>>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|slot| slot.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<T> IntoIterator for HashTable<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(mut self) -> Self::IntoIter {
+        let start = iteration_start(self.entries.len());
+        self.entries.rotate_left(start);
+        IntoIter {
+            entries: self.entries.into_iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn find_insert_remove() {
+        let state = RandomState::new();
+        let hasher = |s: &String| state.hash_one(s);
+        let mut table = HashTable::new();
+        for word in ["foo", "bar", "baz"] {
+            table.insert_unique(state.hash_one(word), word.to_string(), hasher);
+        }
+        assert_eq!(table.len(), 3);
+
+        let hash = state.hash_one("bar");
+        assert_eq!(table.find(hash, |s| s == "bar").map(String::as_str), Some("bar"));
+        assert_eq!(table.find(hash, |s| s == "quo"), None);
+        table.find_mut(hash, |s| s == "bar").unwrap().make_ascii_uppercase();
+        assert_eq!(table.remove(hash, |s| s == "BAR").as_deref(), Some("BAR"));
+        assert_eq!(table.remove(hash, |s| s == "BAR"), None);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.iter().count(), 2);
+    }
+
+    #[test]
+    fn removal_moves_the_last_entry() {
+        let state = RandomState::new();
+        let hasher = |i: &u32| state.hash_one(i);
+        let mut table = HashTable::new();
+        for i in 0..100 {
+            table.insert_unique(hasher(&i), i, hasher);
+        }
+        table.retain(|i| *i % 3 != 0);
+        assert_eq!(table.remove(hasher(&1), |i| *i == 1), Some(1));
+        assert_eq!(table.len(), 65);
+        // every bucket still points at entries that belong in it.
+        for (bucket, indices) in table.buckets().iter().enumerate() {
+            for &index in indices {
+                let slot = &table.entries()[index as usize];
+                assert_eq!(slot.bucket as usize, bucket);
+                assert_eq!(table.bucket_for_hash(hasher(&slot.value)), bucket);
+            }
+        }
+        for i in 2..100 {
+            assert_eq!(table.find(hasher(&i), |e| *e == i).is_some(), i % 3 != 0);
+        }
+    }
+
+    #[test]
+    fn lookup_by_either_key() {
+        // an index over (id, name) rows, the same table holds one slot per key.
+        let rows = [(1, "foo"), (2, "bar"), (3, "baz")];
+        let state = RandomState::new();
+        let mut by_id = HashTable::new();
+        let mut by_name = HashTable::new();
+        for (row, &(id, name)) in rows.iter().enumerate() {
+            by_id.insert_unique(state.hash_one(id), row, |&r| state.hash_one(rows[r].0));
+            by_name.insert_unique(state.hash_one(name), row, |&r| state.hash_one(rows[r].1));
+        }
+
+        let row = by_name.find(state.hash_one("baz"), |&r| rows[r].1 == "baz");
+        assert_eq!(row, Some(&2));
+        let row = by_id.find(state.hash_one(2), |&r| rows[r].0 == 2);
+        assert_eq!(row, Some(&1));
+        assert_eq!(by_id.into_iter().count(), 3);
+    }
+
+    #[cfg(feature = "randomize-iteration")]
+    #[test]
+    fn iteration_order_changes() {
+        let state = RandomState::new();
+        let mut table = HashTable::new();
+        for i in 0..100 {
+            table.insert_unique(state.hash_one(i), i, |i| state.hash_one(i));
+        }
+        let first: Vec<_> = table.iter().collect();
+        // each iteration starts from one of 100 entries, five of them all matching is unlikely.
+        assert!((0..5).any(|_| table.iter().collect::<Vec<_>>() != first));
+        assert_eq!(table.iter().count(), 100);
+        assert_eq!(table.clone().into_iter().count(), 100);
+    }
+}
@@ -0,0 +1,151 @@
+use std::hash::{BuildHasher, Hash};
+
+use crate::{make_hash, Equivalent, HashMap};
+
+/// A batch of changes to a [`HashMap`], kept only if it's committed.
+///
+/// Returned by [`HashMap::transaction`]. Changes are made to the map right away, and every one
+/// logs how to undo it: the key and the value it had before, if any. [`commit`](Self::commit)
+/// drops the log; [`rollback`](Self::rollback), or dropping the transaction, replays it
+/// backwards, putting back every value that was overwritten or removed. The transaction borrows
+/// the map mutably, so nobody else sees the changes until it is committed.
+pub struct Transaction<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    map: &'a mut HashMap<K, V, S>,
+    /// every key changed, with the value it had before the change.
+    undo: Vec<(K, Option<V>)>,
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    /// Starts a transaction: changes made through it are undone unless it's committed.
+    ///
+    /// ```
+    /// use rehash::HashMap;
+    ///
+    /// let mut map = HashMap::new();
+    /// map.insert("port", 80);
+    /// let mut transaction = map.transaction();
+    /// transaction.insert("port", 8080);
+    /// transaction.insert("host", 1);
+    /// transaction.rollback();
+    /// assert_eq!(map.get("port"), Some(&80));
+    /// assert!(!map.contains_key("host"));
+    /// ```
+    pub fn transaction(&mut self) -> Transaction<'_, K, V, S> {
+        Transaction {
+            map: self,
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl<'a, K, V, S> Transaction<'a, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    /// Inserts a key-value pair, returning the old value of the key if it was in the map.
+    ///
+    /// The old value is kept in case of a rollback, so only a reference to it is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<&V> {
+        let old = self.map.insert(key.clone(), value);
+        self.undo.push((key, old));
+        self.undo.last().unwrap().1.as_ref()
+    }
+
+    /// Removes a key from the map, returning its value if it was there.
+    ///
+    /// The value is kept in case of a rollback, so only a reference to it is returned.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        let (key, value) = self.map.table.remove(hash, |(k, _)| key.equivalent(k))?;
+        self.undo.push((key, Some(value)));
+        self.undo.last().unwrap().1.as_ref()
+    }
+
+    /// Returns the value of `key`, changes made by the transaction included.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Keeps the changes made by the transaction.
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+
+    /// Undoes the changes made by the transaction, same as dropping it.
+    pub fn rollback(self) {}
+}
+
+impl<'a, K, V, S> Drop for Transaction<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        while let Some((key, old)) = self.undo.pop() {
+            match old {
+                Some(value) => {
+                    self.map.insert(key, value);
+                }
+                None => {
+                    self.map.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_restores_overwritten_values() {
+        let mut map = HashMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+
+        let mut transaction = map.transaction();
+        assert_eq!(transaction.insert("a", 10), Some(&1));
+        assert_eq!(transaction.insert("a", 100), Some(&10));
+        assert_eq!(transaction.remove("b"), Some(&2));
+        assert_eq!(transaction.insert("c", 3), None);
+        assert_eq!(transaction.remove("d"), None);
+        assert_eq!(transaction.get("a"), Some(&100));
+        drop(transaction);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.get("b"), Some(&2));
+
+        let mut transaction = map.transaction();
+        transaction.remove("a");
+        transaction.insert("b", 20);
+        transaction.insert("c", 3);
+        transaction.commit();
+        assert!(!map.contains_key("a"));
+        assert_eq!(map.get("b"), Some(&20));
+        assert_eq!(map.get("c"), Some(&3));
+    }
+}
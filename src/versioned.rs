@@ -0,0 +1,188 @@
+//! A map keeping a version number, with snapshots of the map as of a version.
+
+use std::hash::Hash;
+
+use crate::cow::{self, CowHashMap};
+use crate::Equivalent;
+
+/// A map whose version goes up with every change, and that hands out [`Snapshot`]s: read-only
+/// views of the map as of the version they were taken at.
+///
+/// The map is a [`CowHashMap`], so a snapshot only shares its buckets. Taking one is a reference
+/// count bump, and the writer copies a bucket the first time it changes it while a snapshot still
+/// holds it. Snapshots are `Send` and `Sync` (for `Send + Sync` keys and values), so a long
+/// running reader on another thread sees one consistent state while the writer carries on.
+pub struct VersionedHashMap<K, V> {
+    map: CowHashMap<K, V>,
+    version: u64,
+}
+
+/// The state of a [`VersionedHashMap`] at a given version.
+pub struct Snapshot<K, V> {
+    map: CowHashMap<K, V>,
+    version: u64,
+}
+
+impl<K, V> VersionedHashMap<K, V> {
+    /// Creates an empty map, at version 0.
+    pub fn new() -> Self {
+        VersionedHashMap {
+            map: CowHashMap::new(),
+            version: 0,
+        }
+    }
+
+    /// Returns the current version: the number of changes made to the map so far.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns a view of the map as it is now, which later changes don't affect.
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        Snapshot {
+            map: self.map.clone(),
+            version: self.version,
+        }
+    }
+
+    /// Returns the number of items that are currently in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<K, V> Default for VersionedHashMap<K, V> {
+    fn default() -> Self {
+        VersionedHashMap::new()
+    }
+}
+
+impl<K, V> VersionedHashMap<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns true if the key is in the map, false otherwise.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+}
+
+impl<K, V> VersionedHashMap<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Inserts a key-value pair, moving the map to the next version. Returns the old value of
+    /// the key, if there was one.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.version += 1;
+        self.map.insert(key, value)
+    }
+
+    /// Removes a key from the map, returning its value. The map moves to the next version only
+    /// if the key was there.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let value = self.map.remove(key)?;
+        self.version += 1;
+        Some(value)
+    }
+}
+
+impl<K, V> Snapshot<K, V> {
+    /// Returns the version of the map the snapshot was taken at.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the number of items in the map as of the snapshot.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> cow::Iter<'_, K, V> {
+        self.map.into_iter()
+    }
+}
+
+impl<K, V> Snapshot<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Returns the value `key` had as of the snapshot.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+}
+
+/// Cloning a snapshot only bumps reference counts.
+impl<K, V> Clone for Snapshot<K, V> {
+    fn clone(&self) -> Self {
+        Snapshot {
+            map: self.map.clone(),
+            version: self.version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn snapshots_keep_their_version() {
+        let mut map = VersionedHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.remove(&1000), None);
+        let snapshot = map.snapshot();
+        assert_eq!(snapshot.version(), 100);
+
+        let reader = thread::spawn(move || {
+            assert_eq!(snapshot.len(), 100);
+            (0..100).all(|i| snapshot.get(&i) == Some(&i))
+        });
+        for i in 0..100 {
+            map.insert(i, i + 1);
+        }
+        map.remove(&0);
+        assert!(reader.join().unwrap());
+
+        assert_eq!(map.version(), 201);
+        assert_eq!(map.len(), 99);
+        assert_eq!(map.snapshot().iter().count(), 99);
+    }
+}
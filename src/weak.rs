@@ -0,0 +1,126 @@
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+use crate::{Equivalent, HashMap};
+
+/// Dead entries are purged on insert once the map holds at least this many entries.
+const MIN_PURGE_AT: usize = 8;
+
+/// A map holding [`Weak`] references to its values, so it never keeps a value alive.
+///
+/// Once the last `Arc` to a value is dropped the entry is dead: lookups ignore it and it gets
+/// dropped by [`purge`](WeakValueHashMap::purge). Inserts also purge every now and then (each
+/// time the map has doubled since the last purge), so dead entries can't pile up forever even if
+/// `purge` is never called.
+pub struct WeakValueHashMap<K, V> {
+    map: HashMap<K, Weak<V>>,
+    /// the next insert purges once the map has reached this size.
+    purge_at: usize,
+}
+
+impl<K, V> WeakValueHashMap<K, V> {
+    pub fn new() -> Self {
+        WeakValueHashMap {
+            map: HashMap::new(),
+            purge_at: MIN_PURGE_AT,
+        }
+    }
+}
+
+impl<K, V> Default for WeakValueHashMap<K, V> {
+    fn default() -> Self {
+        WeakValueHashMap::new()
+    }
+}
+
+impl<K, V> WeakValueHashMap<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Inserts a weak reference to `value` under `key`.
+    ///
+    /// Returns the value previously stored under `key`, if it was still alive.
+    pub fn insert(&mut self, key: K, value: &Arc<V>) -> Option<Arc<V>> {
+        if self.map.len() >= self.purge_at {
+            self.purge();
+            self.purge_at = MIN_PURGE_AT.max(2 * self.map.len());
+        }
+        self.map
+            .insert(key, Arc::downgrade(value))
+            .and_then(|old| old.upgrade())
+    }
+
+    /// Returns the value corresponding to the key, if it is still alive.
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get(key)?.upgrade()
+    }
+
+    /// Returns true if the key is in the map and its value is still alive.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes a key from the map, returning its value if it was still alive.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.remove(key)?.upgrade()
+    }
+
+    /// Drops every entry whose value is no longer alive.
+    pub fn purge(&mut self) {
+        self.map.retain(|_, value| value.strong_count() > 0);
+    }
+
+    /// Returns the number of entries in the map, including dead ones that haven't been purged
+    /// yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_keep_values_alive() {
+        let mut map = WeakValueHashMap::new();
+        let foo = Arc::new(42);
+        let bar = Arc::new(43);
+        map.insert("foo", &foo);
+        map.insert("bar", &bar);
+
+        assert_eq!(map.get("foo"), Some(foo.clone()));
+        drop(foo);
+        assert_eq!(map.get("foo"), None);
+        assert!(!map.contains_key("foo"));
+        assert_eq!(map.len(), 2);
+
+        map.purge();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.remove("bar"), Some(bar));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn insert_purges_dead_entries() {
+        let mut map = WeakValueHashMap::new();
+        for i in 0..1000 {
+            // every value dies right away.
+            map.insert(i, &Arc::new(i));
+        }
+        assert!(map.len() <= MIN_PURGE_AT);
+    }
+}